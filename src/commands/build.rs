@@ -1,5 +1,28 @@
-use crate::utils::display::print_unicode_box;
+use crate::commands::common::{
+    dry_run_arg, env_file_arg, env_var_arg, output_file, output_file_arg, parallelism_arg,
+    server_address, stack_context,
+};
+use crate::commands::report::{CommandReport, ResourceAction, ResourceReport};
+use crate::resource::dry_run::print_rendered_queries;
+use crate::resource::exports::{stack_exports, write_exports};
+use crate::resource::manifest::{Manifest, Resource};
+use crate::resource::operation::{
+    capture_exports, check_exists, check_state, load_queries, render_query, run_statement,
+};
+use crate::resource::query::{Anchor, QueryOptions};
+use crate::resource::scheduler::{run_scheduled, Direction};
+use crate::template::context::StackContext;
+use crate::utils::display::{
+    print_error, print_info, print_line, print_success, print_unicode_box,
+};
+use crate::utils::query::{SessionPool, StackqlSession};
 use clap::{Arg, ArgMatches, Command};
+use colored::*;
+use serde_json::Value as JsonValue;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Instant;
 
 pub fn command() -> Command {
     Command::new("build")
@@ -14,13 +37,213 @@ pub fn command() -> Command {
                 .required(true)
                 .help("Environment to deploy"),
         )
+        .arg(parallelism_arg())
+        .arg(dry_run_arg())
+        .arg(output_file_arg())
+        .arg(env_file_arg())
+        .arg(env_var_arg())
 }
 
 pub fn execute(matches: &ArgMatches) {
     let stack_dir = matches.get_one::<String>("stack_dir").unwrap();
     let stack_env = matches.get_one::<String>("stack_env").unwrap();
+    let parallelism = *matches.get_one::<u16>("parallelism").unwrap() as usize;
+    let mut report = CommandReport::new("build", matches);
+    let output_file = match output_file(matches) {
+        Ok(output_file) => output_file,
+        Err(e) => report.fail(&format!("Invalid --output-file: {}", e)),
+    };
+
+    let manifest = match Manifest::load(Path::new(stack_dir)) {
+        Ok(manifest) => manifest,
+        Err(e) => report.fail(&format!("Failed to load manifest: {}", e)),
+    };
+    report.stack = Some(manifest.name.clone());
+
     print_unicode_box(&format!(
         "Deploying stack: [{}] to environment: [{}]",
-        stack_dir, stack_env
+        manifest.name, stack_env
     ));
+    print_info(&format!("providers: {}", manifest.providers.join(", ")));
+
+    let mut context = match stack_context(matches, &manifest, Path::new(stack_dir), stack_env) {
+        Ok(context) => context,
+        Err(e) => report.fail(&e),
+    };
+
+    if matches.get_flag("dry_run") {
+        let anchors = [
+            Anchor::Exists,
+            Anchor::Create,
+            Anchor::Statecheck,
+            Anchor::Update,
+            Anchor::Exports,
+        ];
+        let rendered = match print_rendered_queries(
+            &manifest,
+            Path::new(stack_dir),
+            stack_env,
+            &mut context,
+            &anchors,
+            false,
+        ) {
+            Ok(rendered) => rendered,
+            Err(e) => report.fail(&format!("Dry run failed: {}", e)),
+        };
+        let resources = rendered
+            .into_iter()
+            .map(|(name, queries)| ResourceReport::rendered(&name, queries))
+            .collect();
+        report.set_resources(resources, manifest.graph.order());
+        print_success("Dry run complete, no queries were executed");
+        if let Some(output_file) = output_file {
+            print_info(&format!(
+                "Exports are not known in a dry run, {} was not written",
+                output_file.display()
+            ));
+        }
+        report.succeed();
+        return;
+    }
+
+    let context = Mutex::new(context);
+    let (server_host, server_port) = server_address(matches);
+    let session = match StackqlSession::open(&server_host, server_port) {
+        Ok(session) => session,
+        Err(e) => report.fail(&format!("Failed to connect to stackql server: {}", e)),
+    };
+    let sessions = SessionPool::new(session);
+    let resources = Mutex::new(Vec::new());
+
+    // A resource starts once every resource it takes exports from has been deployed
+    let result = run_scheduled(
+        &manifest.graph,
+        manifest.graph.order(),
+        Direction::Forward,
+        parallelism,
+        |name| {
+            let resource = manifest.resource(name).unwrap();
+            if parallelism == 1 {
+                print_line(&format!(
+                    "\n{}",
+                    format!("resource: {}", resource.name).bold()
+                ));
+            }
+            let started = Instant::now();
+            let deployed = sessions.with_session(|session| {
+                deploy_resource(Path::new(stack_dir), resource, stack_env, &context, session)
+            });
+            let (resource_report, result) = match deployed {
+                Ok((action, exports)) => {
+                    let mut resource_report = ResourceReport::new(name, action, started);
+                    resource_report.exports = exports;
+                    (resource_report, Ok(()))
+                }
+                Err(e) => (ResourceReport::failed(name, &e, started), Err(e)),
+            };
+            resources.lock().unwrap().push(resource_report);
+            result
+        },
+    );
+    report.set_resources(resources.into_inner().unwrap(), manifest.graph.order());
+
+    if let Err(failures) = result {
+        let names: Vec<String> = failures.iter().map(|(name, _)| name.clone()).collect();
+        for (name, e) in failures {
+            print_error(&format!("Failed to deploy [{}]: {}", name, e));
+        }
+        report.exit_failed(&format!("Failed to deploy [{}]", names.join("], [")));
+    }
+
+    if let Some(output_file) = output_file {
+        let exports = stack_exports(&context.into_inner().unwrap());
+        if let Err(e) = write_exports(&output_file, &exports) {
+            report.fail(&format!("Failed to write exports: {}", e));
+        }
+        print_info(&format!(
+            "Wrote {} exports to {}",
+            exports.len(),
+            output_file.display()
+        ));
+    }
+
+    print_success(&format!("Stack [{}] deployed successfully", manifest.name));
+    report.succeed();
+}
+
+/// Create or update a single resource, then capture its exports into the context,
+/// returns what was done and the exports
+fn deploy_resource(
+    stack_dir: &Path,
+    resource: &Resource,
+    stack_env: &str,
+    context: &Mutex<StackContext>,
+    session: &mut StackqlSession,
+) -> Result<(ResourceAction, BTreeMap<String, JsonValue>), String> {
+    let queries = load_queries(stack_dir, resource)?;
+    let resource_context = context.lock().unwrap().with_resource(resource, stack_env)?;
+
+    let exists = match queries.get(Anchor::Exists) {
+        Some(query) => {
+            print_info(&format!("checking if [{}] exists...", resource.name));
+            check_exists(&render_query(resource, query, &resource_context)?, session)?
+        }
+        _none => false,
+    };
+
+    let statecheck = queries.get(Anchor::Statecheck);
+    let action = if !exists {
+        let create = queries.get(Anchor::Create).ok_or_else(|| {
+            "resource does not exist and no 'create' query is defined".to_string()
+        })?;
+        print_info(&format!("creating [{}]...", resource.name));
+        run_statement(&render_query(resource, create, &resource_context)?, session)?;
+        ResourceAction::Created
+    } else {
+        let in_desired_state = match statecheck {
+            Some(query) => {
+                print_info(&format!("checking state of [{}]...", resource.name));
+                let sql = render_query(resource, query, &resource_context)?;
+                check_state(&sql, &QueryOptions::default(), session)?
+            }
+            _none => false,
+        };
+
+        if in_desired_state {
+            print_info(&format!("[{}] is in the desired state", resource.name));
+            ResourceAction::Unchanged
+        } else if let Some(update) = queries.get(Anchor::Update) {
+            print_info(&format!("updating [{}]...", resource.name));
+            run_statement(&render_query(resource, update, &resource_context)?, session)?;
+            ResourceAction::Updated
+        } else {
+            print_info(&format!(
+                "[{}] exists and no 'update' query is defined, skipping",
+                resource.name
+            ));
+            ResourceAction::Unchanged
+        }
+    };
+
+    if action != ResourceAction::Unchanged {
+        if let Some(query) = statecheck {
+            print_info(&format!(
+                "waiting for [{}] to reach the desired state...",
+                resource.name
+            ));
+            let sql = render_query(resource, query, &resource_context)?;
+            if !check_state(&sql, &query.options, session)? {
+                return Err(format!(
+                    "state check failed after {} attempts",
+                    query.options.retries.max(1)
+                ));
+            }
+        }
+    }
+
+    let exports = capture_exports(resource, &queries, &resource_context, session)?;
+    context.lock().unwrap().insert_exports(&exports);
+
+    print_success(&format!("[{}] deployed", resource.name));
+    Ok((action, exports))
 }
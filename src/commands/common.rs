@@ -0,0 +1,211 @@
+use crate::resource::exports::ExportsFormat;
+use crate::resource::imports::resolve_imports;
+use crate::resource::manifest::{Manifest, MANIFEST_FILE_NAME};
+use crate::template::context::StackContext;
+use crate::utils::env::{parse_env_var, stack_vars};
+use crate::utils::server::{DEFAULT_SERVER_HOST, DEFAULT_SERVER_PORT};
+use crate::utils::stackql::normalize_version;
+use clap::{Arg, ArgAction, ArgMatches};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Global `--server-host` argument, the host of the stackql server every command uses
+pub fn server_host_arg() -> Arg {
+    Arg::new("server_host")
+        .long("server-host")
+        .env("STACKQL_SERVER_HOST")
+        .value_name("HOST")
+        .help("Host of the stackql server, servers on other hosts are never started or stopped [default: localhost]")
+        .global(true)
+        .action(ArgAction::Set)
+}
+
+/// Global `--server-port` argument, the port of the stackql server every command uses
+pub fn server_port_arg() -> Arg {
+    Arg::new("server_port")
+        .long("server-port")
+        .env("STACKQL_SERVER_PORT")
+        .value_name("PORT")
+        .help("Port of the stackql server [default: 5444]")
+        .value_parser(clap::value_parser!(u16))
+        .global(true)
+        .action(ArgAction::Set)
+}
+
+/// Global `--keep-server` flag, leaves a server started by the command running afterwards
+pub fn keep_server_arg() -> Arg {
+    Arg::new("keep_server")
+        .long("keep-server")
+        .help("Leave a stackql server started by this command running when it finishes")
+        .global(true)
+        .action(ArgAction::SetTrue)
+}
+
+/// Global `--offline` flag, fail instead of downloading the stackql binary
+pub fn offline_arg() -> Arg {
+    Arg::new("offline")
+        .long("offline")
+        .help("Fail instead of downloading the stackql binary when it is not installed")
+        .global(true)
+        .action(ArgAction::SetTrue)
+}
+
+/// Global `--stackql-version` argument, pins the stackql release commands run with
+pub fn stackql_version_arg() -> Arg {
+    Arg::new("stackql_version")
+        .long("stackql-version")
+        .value_name("VERSION")
+        .help("stackql release to use, e.g. v0.5.612, overrides the manifest's stackql_version")
+        .global(true)
+        .action(ArgAction::Set)
+}
+
+/// Global `--output` argument, `json` prints one document with the results of info,
+/// build, test, plan and teardown on stdout
+pub fn output_arg() -> Arg {
+    Arg::new("output")
+        .long("output")
+        .value_name("FORMAT")
+        .value_parser(["text", "json"])
+        .default_value("text")
+        .help("Output format, json prints the command's results as a single JSON document on stdout and everything else on stderr")
+        .global(true)
+        .action(ArgAction::Set)
+}
+
+/// The pinned stackql release, normalized to `v1.2.3`, and where it was pinned. The
+/// `--stackql-version` flag takes precedence over the `stackql_version` of the stack.
+pub fn pinned_stackql_version(matches: &ArgMatches) -> Result<Option<(String, String)>, String> {
+    if let Some(version) = matches.get_one::<String>("stackql_version") {
+        return match normalize_version(version) {
+            Some(normalized) => Ok(Some((normalized, "--stackql-version".to_string()))),
+            _none => Err(format!(
+                "invalid --stackql-version '{}', expected a release such as v0.5.612",
+                version
+            )),
+        };
+    }
+
+    let stack_dir = match matches.try_get_one::<String>("stack_dir").ok().flatten() {
+        Some(stack_dir) => stack_dir,
+        _none => return Ok(None),
+    };
+    // an invalid version is reported when the command loads the manifest
+    Ok(Manifest::pinned_stackql_version(Path::new(stack_dir))
+        .and_then(|version| normalize_version(&version))
+        .map(|version| (version, format!("{}/{}", stack_dir, MANIFEST_FILE_NAME))))
+}
+
+/// Host and port of the stackql server selected by the global server arguments
+pub fn server_address(matches: &ArgMatches) -> (String, u16) {
+    let host = matches
+        .get_one::<String>("server_host")
+        .map(String::as_str)
+        .unwrap_or(DEFAULT_SERVER_HOST);
+    let port = matches
+        .get_one::<u16>("server_port")
+        .copied()
+        .unwrap_or(DEFAULT_SERVER_PORT);
+    (host.to_string(), port)
+}
+
+/// `--env-file` argument shared by the stack commands
+pub fn env_file_arg() -> Arg {
+    Arg::new("env_file")
+        .long("env-file")
+        .help("Dotenv file with variables for the stack [default: <stack_dir>/.env]")
+        .action(ArgAction::Set)
+}
+
+/// `-e/--var KEY=VALUE` argument shared by the stack commands
+pub fn env_var_arg() -> Arg {
+    Arg::new("env_var")
+        .short('e')
+        .long("var")
+        .value_name("KEY=VALUE")
+        .help("Set a variable for the stack, can be repeated")
+        .action(ArgAction::Append)
+}
+
+/// `--dry-run` flag for commands that would otherwise change resources
+pub fn dry_run_arg() -> Arg {
+    Arg::new("dry_run")
+        .long("dry-run")
+        .help("Render and print every query without executing anything")
+        .action(ArgAction::SetTrue)
+}
+
+/// `--parallelism N` argument for commands that can process independent resources concurrently
+pub fn parallelism_arg() -> Arg {
+    Arg::new("parallelism")
+        .long("parallelism")
+        .value_name("N")
+        .help("Number of independent resources to process concurrently")
+        .default_value("1")
+        .value_parser(clap::value_parser!(u16).range(1..))
+        .action(ArgAction::Set)
+}
+
+/// `--output-file` argument for commands that capture the stack's exports
+pub fn output_file_arg() -> Arg {
+    Arg::new("output_file")
+        .long("output-file")
+        .value_name("FILE")
+        .help("Write the stack's exports to FILE when the command succeeds, as JSON for a .json file or dotenv for a .env file")
+        .action(ArgAction::Set)
+}
+
+/// The `--output-file` path, after checking its format can be told from its name
+pub fn output_file(matches: &ArgMatches) -> Result<Option<PathBuf>, String> {
+    match matches.get_one::<String>("output_file") {
+        Some(path) => {
+            let path = PathBuf::from(path);
+            ExportsFormat::from_path(&path)?;
+            Ok(Some(path))
+        }
+        _none => Ok(None),
+    }
+}
+
+/// Load the variables of a stack command, resolve the stack's imports and build its
+/// template context. Variables come from the process environment, the env file, then
+/// `-e`, in increasing precedence. A dry run resolves imports without a server, using placeholders
+/// for exports that would need a query.
+pub fn stack_context(
+    matches: &ArgMatches,
+    manifest: &Manifest,
+    stack_dir: &Path,
+    stack_env: &str,
+) -> Result<StackContext, String> {
+    let overrides = var_overrides(matches)
+        .map_err(|e| format!("Failed to load environment variables: {}", e))?;
+    let env_file = matches.get_one::<String>("env_file").map(Path::new);
+    let env_vars = stack_vars(stack_dir, env_file, &overrides)
+        .map_err(|e| format!("Failed to load environment variables: {}", e))?;
+
+    let dry_run = matches
+        .try_get_one::<bool>("dry_run")
+        .ok()
+        .flatten()
+        .copied()
+        .unwrap_or(false);
+    let (server_host, server_port) = server_address(matches);
+    let server = (!dry_run).then_some((server_host.as_str(), server_port));
+    let imports = resolve_imports(
+        manifest, stack_dir, stack_env, &env_vars, &overrides, server,
+    )
+    .map_err(|e| format!("Failed to resolve imports: {}", e))?;
+
+    StackContext::for_stack(manifest, stack_env, &env_vars, &imports)
+        .map_err(|e| format!("Failed to prepare stack context: {}", e))
+}
+
+/// The `-e` variables, they take precedence over the process environment and env files
+pub fn var_overrides(matches: &ArgMatches) -> Result<BTreeMap<String, String>, String> {
+    matches
+        .get_many::<String>("env_var")
+        .into_iter()
+        .flatten()
+        .map(|pair| parse_env_var(pair))
+        .collect()
+}
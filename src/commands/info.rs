@@ -1,16 +1,25 @@
-use crate::utils::display::print_unicode_box;
-use crate::utils::platform::get_platform;
-use crate::utils::server::{get_server_pid, is_server_running};
-use crate::utils::stackql::{get_installed_providers, get_stackql_path, get_version};
-use clap::Command;
+use crate::commands::common::{pinned_stackql_version, server_address};
+use crate::commands::report::print_json;
+use crate::utils::display::{json_output, print_unicode_box};
+use crate::utils::platform::get_target;
+use crate::utils::server::{get_server_pid, is_local_host, is_server_running};
+use crate::utils::stackql::{
+    get_installed_providers, get_stackql_path, get_version, normalize_version,
+};
+use clap::{Arg, ArgMatches, Command};
 use colored::*;
+use serde_json::json;
 use std::process;
 
 pub fn command() -> Command {
-    Command::new("info").about("Display version information")
+    Command::new("info")
+        .about("Display version information")
+        .arg(Arg::new("stack_dir").help(
+            "Stack directory whose pinned stackql_version is checked against the installed version",
+        ))
 }
 
-pub fn execute() {
+pub fn execute(matches: &ArgMatches) {
     print_unicode_box("📋 Getting program information...");
 
     // Get stackql version
@@ -18,12 +27,15 @@ pub fn execute() {
         Ok(info) => info,
         Err(e) => {
             eprintln!("{}", format!("Error: {}", e).red());
+            if json_output() {
+                print_json(&json!({ "command": "info", "success": false, "error": e }));
+            }
             process::exit(1);
         }
     };
 
     // Get platform
-    let platform = get_platform();
+    let platform = get_target();
 
     // Get binary path
     let binary_path = match get_stackql_path() {
@@ -31,18 +43,66 @@ pub fn execute() {
         _none => "Not found".to_string(),
     };
 
-    // Check server status
-    let default_port = 5444;
-    let server_running = is_server_running(default_port);
-    let server_pid = if server_running {
-        get_server_pid(default_port).unwrap_or(0)
-    } else {
-        0
+    // Check server status, servers on other hosts are not managed by this machine
+    let (server_host, server_port) = server_address(matches);
+    let server_local = is_local_host(&server_host);
+    let server_running = server_local && is_server_running(server_port);
+    let server_pid = match get_server_pid(server_port) {
+        Some(pid) => pid.to_string(),
+        _none => "unknown (not started by stackql-deploy)".to_string(),
     };
 
     // Get installed providers
     let providers = get_installed_providers().unwrap_or_default();
 
+    // Warn when the installed stackql is not the release the stack pins
+    let version_warning = match pinned_stackql_version(matches) {
+        Ok(Some((pinned, source)))
+            if normalize_version(&version_info.version).as_ref() != Some(&pinned) =>
+        {
+            Some(format!(
+                "installed version {} differs from {} pinned by {}",
+                version_info.version, pinned, source
+            ))
+        }
+        Err(e) => Some(e),
+        _ => None,
+    };
+
+    if json_output() {
+        let status = if !server_local {
+            "remote"
+        } else if server_running {
+            "running"
+        } else {
+            "not_running"
+        };
+        print_json(&json!({
+            "command": "info",
+            "success": true,
+            "version": "0.1.0",
+            "stackql": {
+                "version": version_info.version,
+                "sha": version_info.sha,
+                "platform": platform.to_string(),
+                "binary_path": get_stackql_path(),
+                "warning": version_warning,
+            },
+            "server": {
+                "host": server_host,
+                "port": server_port,
+                "local": server_local,
+                "status": status,
+                "pid": get_server_pid(server_port).filter(|_| server_running),
+            },
+            "providers": providers
+                .iter()
+                .map(|provider| json!({ "name": provider.name, "version": provider.version }))
+                .collect::<Vec<_>>(),
+        }));
+        return;
+    }
+
     // Print information
     println!("{}", "stackql-deploy CLI".green().bold());
     println!("  Version: 0.1.0\n");
@@ -50,14 +110,22 @@ pub fn execute() {
     println!("{}", "StackQL Library".green().bold());
     println!("  Version: {}", version_info.version);
     println!("  SHA: {}", version_info.sha);
-    println!("  Platform: {:?}", platform);
+    println!("  Platform: {}", platform);
     println!("  Binary Path: {}", binary_path);
 
+    if let Some(warning) = version_warning {
+        println!("  {}", format!("Warning: {}", warning).yellow());
+    }
+
     println!("\n{}", "StackQL Server".green().bold());
-    if server_running {
+    if !server_local {
+        println!("  Status: {}", "Remote".green());
+        println!("  Host: {}", server_host);
+        println!("  Port: {}", server_port);
+    } else if server_running {
         println!("  Status: {}", "Running".green());
         println!("  PID: {}", server_pid);
-        println!("  Port: {}", default_port);
+        println!("  Port: {}", server_port);
     } else {
         println!("  Status: {}", "Not Running".yellow());
     }
@@ -1,3 +1,6 @@
+use crate::resource::manifest::Manifest;
+use crate::resource::props::prop_value;
+use crate::template::engine::render_template;
 use crate::utils::display::print_unicode_box;
 use clap::{Arg, ArgAction, ArgMatches, Command};
 use colored::*;
@@ -7,7 +10,7 @@ use std::collections::HashSet;
 use std::fs;
 use std::io::Write;
 use std::path::Path;
-use tera::{Context, Tera};
+use tera::Context;
 
 // The base URL for GitHub template repository
 const GITHUB_TEMPLATE_BASE: &str =
@@ -145,6 +148,7 @@ pub fn execute(matches: &ArgMatches) {
                 "{}",
                 format!("Project {} initialized successfully.", stack_name).green()
             );
+            check_env_values(&stack_name, &env);
         }
         Err(e) => {
             eprintln!("{}", format!("Error initializing project: {}", e).red());
@@ -152,6 +156,39 @@ pub fn execute(matches: &ArgMatches) {
     }
 }
 
+// Warn about props the generated manifest does not define for the chosen environment
+fn check_env_values(stack_name: &str, env: &str) {
+    let manifest = match Manifest::load(Path::new(stack_name)) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            println!(
+                "{}",
+                format!("Warning: generated manifest is not valid: {}", e).yellow()
+            );
+            return;
+        }
+    };
+
+    let missing: Vec<String> = manifest
+        .resources
+        .iter()
+        .flat_map(|resource| {
+            resource
+                .props
+                .iter()
+                .filter(|prop| prop_value(prop, env).is_err())
+                .map(move |prop| format!("{}.{}", resource.name, prop.name))
+        })
+        .collect();
+
+    if !missing.is_empty() {
+        println!("{}", format!(
+            "Warning: no value defined for environment '{}' in: {}, add a '{}' entry to their 'values' before deploying",
+            env, missing.join(", "), env
+        ).yellow());
+    }
+}
+
 fn validate_provider(provider: Option<&str>) -> String {
     let supported: HashSet<&str> = SUPPORTED_PROVIDERS.iter().cloned().collect();
 
@@ -368,13 +405,3 @@ fn create_readme_file(
 
     Ok(())
 }
-
-fn render_template(template_str: &str, context: &Context) -> Result<String, String> {
-    // Create a one-off Tera instance for rendering a single template
-    let mut tera = Tera::default();
-    tera.add_raw_template("template", template_str)
-        .map_err(|e| format!("Failed to add template: {}", e))?;
-
-    tera.render("template", context)
-        .map_err(|e| format!("Failed to render template: {}", e))
-}
@@ -1,7 +1,9 @@
 pub mod build;
+pub mod common;
 pub mod info;
 pub mod init;
 pub mod plan;
+pub mod report;
 pub mod shell;
 pub mod start_server;
 pub mod stop_server;
@@ -1,11 +1,257 @@
-use crate::utils::display::print_unicode_box;
-use clap::Command;
+use crate::commands::common::{env_file_arg, env_var_arg, server_address, stack_context};
+use crate::commands::report::{CommandReport, ResourceAction, ResourceReport};
+use crate::resource::manifest::{Manifest, Resource};
+use crate::resource::operation::{
+    capture_exports, check_exists, check_state, load_queries, render_query, UNKNOWN_EXPORT,
+};
+use crate::resource::query::{Anchor, QueryOptions};
+use crate::template::context::StackContext;
+use crate::utils::display::{json_output, print_line, print_unicode_box};
+use crate::utils::query::StackqlSession;
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use colored::*;
+use serde_json::Value as JsonValue;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::Instant;
 
 pub fn command() -> Command {
-    Command::new("plan").about("Plan infrastructure changes (coming soon)")
+    Command::new("plan")
+        .about("Preview the changes build would make, without executing them")
+        .arg(
+            Arg::new("stack_dir")
+                .required(true)
+                .help("Path to stack directory"),
+        )
+        .arg(
+            Arg::new("stack_env")
+                .required(true)
+                .help("Environment to plan for"),
+        )
+        .arg(
+            Arg::new("graph")
+                .long("graph")
+                .value_name("FORMAT")
+                .value_parser(["text", "dot"])
+                .help("Print the resource dependency graph instead of evaluating the plan")
+                .action(ArgAction::Set),
+        )
+        .arg(env_file_arg())
+        .arg(env_var_arg())
 }
 
-pub fn execute() {
-    print_unicode_box("🔮 Infrastructure planning (coming soon)...");
-    println!("The 'plan' feature is coming soon!");
+/// What build would do to a resource
+enum PlannedAction {
+    Create(String),
+    Update(String),
+    /// The statecheck failed and there is no `update` query to run
+    UpdateUnavailable,
+    /// The resource exists and there is neither a `statecheck` nor an `update` query,
+    /// build leaves it as it is without checking its state
+    Unchecked,
+    NoOp,
+}
+
+pub fn execute(matches: &ArgMatches) {
+    let stack_dir = matches.get_one::<String>("stack_dir").unwrap();
+    let stack_env = matches.get_one::<String>("stack_env").unwrap();
+    let mut report = CommandReport::new("plan", matches);
+
+    let manifest = match Manifest::load(Path::new(stack_dir)) {
+        Ok(manifest) => manifest,
+        Err(e) => report.fail(&format!("Failed to load manifest: {}", e)),
+    };
+    report.stack = Some(manifest.name.clone());
+
+    match matches.get_one::<String>("graph").map(String::as_str) {
+        Some(_) if json_output() => {
+            report.fail("--graph prints the graph itself, it cannot be combined with --output json")
+        }
+        Some("dot") => {
+            println!("{}", manifest.graph.to_dot(&manifest.name));
+            return;
+        }
+        Some(_) => {
+            print_unicode_box(&format!("🔗 Resource dependencies: [{}]", manifest.name));
+            println!("{}", manifest.graph.to_text());
+            return;
+        }
+        _none => {}
+    }
+
+    print_unicode_box(&format!(
+        "🔮 Planning stack: [{}] for environment: [{}]",
+        manifest.name, stack_env
+    ));
+
+    let mut context = match stack_context(matches, &manifest, Path::new(stack_dir), stack_env) {
+        Ok(context) => context,
+        Err(e) => report.fail(&e),
+    };
+
+    let (server_host, server_port) = server_address(matches);
+    let mut session = match StackqlSession::open(&server_host, server_port) {
+        Ok(session) => session,
+        Err(e) => report.fail(&format!("Failed to connect to stackql server: {}", e)),
+    };
+
+    let (mut creates, mut updates, mut unchanged) = (0, 0, 0);
+    for resource in manifest.ordered_resources() {
+        let started = Instant::now();
+        let (action, exports) = match plan_resource(
+            Path::new(stack_dir),
+            resource,
+            stack_env,
+            &mut context,
+            &mut session,
+        ) {
+            Ok(planned) => planned,
+            Err(e) => {
+                report
+                    .resources
+                    .push(ResourceReport::failed(&resource.name, &e, started));
+                report.fail(&format!("Failed to plan [{}]: {}", resource.name, e));
+            }
+        };
+
+        let mut resource_report = match action {
+            PlannedAction::Create(sql) => {
+                creates += 1;
+                print_line(&format!(
+                    "\n{} {}",
+                    "+ create".green().bold(),
+                    resource.name.bold()
+                ));
+                print_statement(&sql);
+                let mut resource_report =
+                    ResourceReport::new(&resource.name, ResourceAction::Create, started);
+                resource_report.sql = Some(sql);
+                resource_report
+            }
+            PlannedAction::Update(sql) => {
+                updates += 1;
+                print_line(&format!(
+                    "\n{} {}",
+                    "~ update".yellow().bold(),
+                    resource.name.bold()
+                ));
+                print_statement(&sql);
+                let mut resource_report =
+                    ResourceReport::new(&resource.name, ResourceAction::Update, started);
+                resource_report.sql = Some(sql);
+                resource_report
+            }
+            PlannedAction::UpdateUnavailable => {
+                unchanged += 1;
+                let message = "not in the desired state, but no 'update' query is defined";
+                print_line(&format!(
+                    "\n{} {} ({})",
+                    "! drift".red().bold(),
+                    resource.name.bold(),
+                    message
+                ));
+                let mut resource_report =
+                    ResourceReport::new(&resource.name, ResourceAction::Drift, started);
+                resource_report.message = Some(message.to_string());
+                resource_report
+            }
+            PlannedAction::Unchecked => {
+                unchanged += 1;
+                let message = "exists, no 'statecheck' or 'update' query is defined";
+                print_line(&format!(
+                    "\n{} {} ({})",
+                    "= no changes".bold(),
+                    resource.name.bold(),
+                    message
+                ));
+                let mut resource_report =
+                    ResourceReport::new(&resource.name, ResourceAction::NoChange, started);
+                resource_report.message = Some(message.to_string());
+                resource_report
+            }
+            PlannedAction::NoOp => {
+                unchanged += 1;
+                print_line(&format!(
+                    "\n{} {}",
+                    "= no changes".bold(),
+                    resource.name.bold()
+                ));
+                ResourceReport::new(&resource.name, ResourceAction::NoChange, started)
+            }
+        };
+        resource_report.exports = exports;
+        report.resources.push(resource_report);
+    }
+
+    print_line(&format!(
+        "\n{}",
+        format!(
+            "Plan: {} to create, {} to update, {} unchanged.",
+            creates, updates, unchanged
+        )
+        .bold()
+    ));
+    report.succeed();
+}
+
+/// Evaluate a resource's `exists` and `statecheck` queries and work out what build would do,
+/// along with the exports of a resource that already exists
+fn plan_resource(
+    stack_dir: &Path,
+    resource: &Resource,
+    stack_env: &str,
+    context: &mut StackContext,
+    session: &mut StackqlSession,
+) -> Result<(PlannedAction, BTreeMap<String, JsonValue>), String> {
+    let queries = load_queries(stack_dir, resource)?;
+    let resource_context = context.with_resource(resource, stack_env)?;
+
+    // Queries that depend on resources which do not exist yet cannot be evaluated
+    let exists = match queries.get(Anchor::Exists) {
+        Some(query) => {
+            let sql = render_query(resource, query, &resource_context)?;
+            !sql.contains(UNKNOWN_EXPORT) && check_exists(&sql, session)?
+        }
+        _none => false,
+    };
+
+    if !exists {
+        let create = queries.get(Anchor::Create).ok_or_else(|| {
+            "resource does not exist and no 'create' query is defined".to_string()
+        })?;
+        let sql = render_query(resource, create, &resource_context)?;
+        for name in &resource.exports {
+            context.insert_export(name, JsonValue::String(UNKNOWN_EXPORT.to_string()));
+        }
+        return Ok((PlannedAction::Create(sql), BTreeMap::new()));
+    }
+
+    let statecheck = queries.get(Anchor::Statecheck);
+    let in_desired_state = match statecheck {
+        Some(query) => {
+            let sql = render_query(resource, query, &resource_context)?;
+            check_state(&sql, &QueryOptions::default(), session)?
+        }
+        _none => false,
+    };
+
+    let exports = capture_exports(resource, &queries, &resource_context, session)?;
+    context.insert_exports(&exports);
+
+    if in_desired_state {
+        return Ok((PlannedAction::NoOp, exports));
+    }
+
+    let action = match queries.get(Anchor::Update) {
+        Some(update) => PlannedAction::Update(render_query(resource, update, &resource_context)?),
+        _none if statecheck.is_none() => PlannedAction::Unchecked,
+        _none => PlannedAction::UpdateUnavailable,
+    };
+    Ok((action, exports))
+}
+
+fn print_statement(sql: &str) {
+    for line in sql.lines() {
+        print_line(&format!("    {}", line.dimmed()));
+    }
 }
@@ -0,0 +1,196 @@
+use crate::commands::common::server_address;
+use crate::utils::display::{json_output, print_error};
+use crate::utils::server::{exit_stopping_server, get_server_pid, is_local_host};
+use clap::ArgMatches;
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+use std::collections::BTreeMap;
+use std::time::Instant;
+
+/// What a command did, or would do, to a resource
+#[derive(Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ResourceAction {
+    // build
+    Created,
+    Updated,
+    Unchanged,
+    // teardown
+    Deleted,
+    NotFound,
+    // test
+    Passed,
+    // plan
+    Create,
+    Update,
+    Drift,
+    NoChange,
+    // build and teardown with --dry-run, the queries were rendered but not run
+    Rendered,
+    // any command, the resource could not be processed
+    Failed,
+    // any command, the resource was not started because another one failed
+    NotRun,
+}
+
+/// Result for a single resource in a command report
+#[derive(Serialize)]
+pub struct ResourceReport {
+    pub name: String,
+    pub action: ResourceAction,
+    pub duration_ms: u64,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub exports: BTreeMap<String, JsonValue>,
+    /// The statement plan would run
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sql: Option<String>,
+    /// The queries a dry run rendered, by anchor
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub queries: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl ResourceReport {
+    pub fn new(name: &str, action: ResourceAction, started: Instant) -> Self {
+        Self {
+            name: name.to_string(),
+            action,
+            duration_ms: started.elapsed().as_millis() as u64,
+            exports: BTreeMap::new(),
+            sql: None,
+            queries: BTreeMap::new(),
+            message: None,
+            error: None,
+        }
+    }
+
+    /// Report of a dry run, with the queries that were rendered
+    pub fn rendered(name: &str, queries: BTreeMap<String, String>) -> Self {
+        Self {
+            queries,
+            ..Self::new(name, ResourceAction::Rendered, Instant::now())
+        }
+    }
+
+    pub fn failed(name: &str, error: &str, started: Instant) -> Self {
+        Self {
+            error: Some(error.to_string()),
+            ..Self::new(name, ResourceAction::Failed, started)
+        }
+    }
+}
+
+/// The stackql server a command used
+#[derive(Serialize)]
+pub struct ServerReport {
+    pub host: String,
+    pub port: u16,
+    pub local: bool,
+    /// Set when the server was started by stackql-deploy
+    pub pid: Option<u32>,
+}
+
+/// Machine readable result of a stack command, printed to stdout with `--output json`
+#[derive(Serialize)]
+pub struct CommandReport {
+    pub command: String,
+    pub stack: Option<String>,
+    pub environment: Option<String>,
+    pub success: bool,
+    pub dry_run: bool,
+    pub duration_ms: u64,
+    pub server: ServerReport,
+    pub resources: Vec<ResourceReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip)]
+    started: Instant,
+}
+
+impl CommandReport {
+    /// Start timing a command, the environment and server come from its arguments
+    pub fn new(command: &str, matches: &ArgMatches) -> Self {
+        let (host, port) = server_address(matches);
+        Self {
+            command: command.to_string(),
+            stack: None,
+            environment: matches
+                .try_get_one::<String>("stack_env")
+                .ok()
+                .flatten()
+                .cloned(),
+            success: false,
+            dry_run: matches
+                .try_get_one::<bool>("dry_run")
+                .ok()
+                .flatten()
+                .copied()
+                .unwrap_or(false),
+            duration_ms: 0,
+            server: ServerReport {
+                local: is_local_host(&host),
+                host,
+                port,
+                pid: None,
+            },
+            resources: Vec::new(),
+            error: None,
+            started: Instant::now(),
+        }
+    }
+
+    /// Put resource reports in the order the resources appear in `order`, resources in
+    /// `order` without a report were never started and are reported as `not_run`
+    pub fn set_resources(&mut self, mut resources: Vec<ResourceReport>, order: &[String]) {
+        let now = Instant::now();
+        for name in order {
+            if !resources.iter().any(|resource| resource.name == *name) {
+                resources.push(ResourceReport::new(name, ResourceAction::NotRun, now));
+            }
+        }
+        resources.sort_by_key(|resource| order.iter().position(|name| *name == resource.name));
+        self.resources = resources;
+    }
+
+    /// Emit the report of a successful command
+    pub fn succeed(mut self) {
+        self.success = true;
+        self.emit();
+    }
+
+    /// Print the error, then emit the report of the failed command and exit
+    pub fn fail(self, message: &str) -> ! {
+        print_error(message);
+        self.exit_failed(message)
+    }
+
+    /// Emit the report of the failed command and exit, for errors already printed
+    pub fn exit_failed(mut self, message: &str) -> ! {
+        self.success = false;
+        self.error = Some(message.to_string());
+        self.emit();
+        exit_stopping_server(1)
+    }
+
+    fn emit(&mut self) {
+        if !json_output() {
+            return;
+        }
+        self.duration_ms = self.started.elapsed().as_millis() as u64;
+        if self.server.local {
+            self.server.pid = get_server_pid(self.server.port);
+        }
+        print_json(self);
+    }
+}
+
+/// Print a document on stdout, the only thing written there with `--output json`
+pub fn print_json<T: Serialize>(document: &T) {
+    match serde_json::to_string_pretty(document) {
+        Ok(json) => println!("{}", json),
+        Err(e) => print_error(&format!("Failed to serialize output: {}", e)),
+    }
+}
@@ -1,10 +1,9 @@
+use crate::commands::common::server_address;
 use crate::utils::display::print_unicode_box;
-use crate::utils::query::{execute_query, QueryResult};
-use crate::utils::server::{is_server_running, start_server, ServerOptions};
+use crate::utils::query::{ColumnType, QueryResult, StackqlSession};
+use crate::utils::server::{is_local_host, is_server_running, start_server, ServerOptions};
 use clap::{Arg, ArgAction, ArgMatches, Command};
 use colored::*;
-use postgres::Client;
-use postgres::NoTls;
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
 use std::process;
@@ -16,16 +15,14 @@ pub fn command() -> Command {
             Arg::new("port")
                 .short('p')
                 .long("port")
-                .help("Port to connect to")
-                .default_value("5444")
+                .help("Port to connect to, overrides --server-port")
+                .value_parser(clap::value_parser!(u16))
                 .action(ArgAction::Set),
         )
         .arg(
             Arg::new("host")
-                .short('h')
                 .long("host")
-                .help("Host to connect to")
-                .default_value("localhost")
+                .help("Host to connect to, overrides --server-host")
                 .action(ArgAction::Set),
         )
 }
@@ -33,16 +30,14 @@ pub fn command() -> Command {
 pub fn execute(matches: &ArgMatches) {
     print_unicode_box("🔗 Launching interactive shell...");
 
+    let (server_host, server_port) = server_address(matches);
     let port = matches
-        .get_one::<String>("port")
-        .unwrap_or(&"5444".to_string())
-        .parse::<u16>()
-        .unwrap_or(5444);
+        .get_one::<u16>("port")
+        .copied()
+        .unwrap_or(server_port);
+    let host = matches.get_one::<String>("host").unwrap_or(&server_host);
 
-    let localhost = String::from("localhost");
-    let host = matches.get_one::<String>("host").unwrap_or(&localhost);
-
-    if host == "localhost" && !is_server_running(port) {
+    if is_local_host(host) && !is_server_running(port) {
         println!("{}", "Server not running. Starting server...".yellow());
         let options = ServerOptions {
             port,
@@ -60,12 +55,8 @@ pub fn execute(matches: &ArgMatches) {
         }
     }
 
-    let connection_string = format!(
-        "host={} port={} user=postgres dbname=stackql application_name=stackql",
-        host, port
-    );
-    let _client = match Client::connect(&connection_string, NoTls) {
-        Ok(client) => client,
+    let mut session = match StackqlSession::open(host, port) {
+        Ok(session) => session,
         Err(e) => {
             eprintln!("{}", format!("Failed to connect to server: {}", e).red());
             process::exit(1);
@@ -97,13 +88,9 @@ pub fn execute(matches: &ArgMatches) {
                     break;
                 }
 
-                match execute_query(input, port) {
+                match session.query(input) {
                     Ok(result) => match result {
-                        QueryResult::Data {
-                            columns,
-                            rows,
-                            notices: _,
-                        } => {
+                        QueryResult::Data { columns, rows } => {
                             print_table(columns, rows);
                         }
                         QueryResult::Command(cmd) => {
@@ -117,6 +104,10 @@ pub fn execute(matches: &ArgMatches) {
                         eprintln!("{}", format!("Error: {}", e).red());
                     }
                 }
+
+                for notice in session.notices() {
+                    println!("{}", format!("NOTICE: {}", notice).yellow());
+                }
             }
             Err(ReadlineError::Interrupted) => {
                 println!("CTRL-C");
@@ -141,9 +132,10 @@ fn print_table(
     rows: Vec<crate::utils::query::QueryResultRow>,
 ) {
     let mut column_widths: Vec<usize> = columns.iter().map(|col| col.name.len()).collect();
+    let rows: Vec<Vec<String>> = rows.iter().map(|row| row.display_values()).collect();
 
     for row in &rows {
-        for (i, value) in row.values.iter().enumerate() {
+        for (i, value) in row.iter().enumerate() {
             if i < column_widths.len() && value.len() > column_widths[i] {
                 column_widths[i] = value.len();
             }
@@ -179,9 +171,14 @@ fn print_table(
     let row_count = rows.len();
     for row in rows {
         print!("|");
-        for (i, value) in row.values.iter().enumerate() {
+        for (i, value) in row.iter().enumerate() {
             if i < column_widths.len() {
-                print!(" {}{} |", value, " ".repeat(column_widths[i] - value.len()));
+                let padding = " ".repeat(column_widths[i] - value.len());
+                match columns[i].column_type {
+                    // numbers are right aligned
+                    ColumnType::Integer | ColumnType::Float => print!(" {}{} |", padding, value),
+                    _ => print!(" {}{} |", value, padding),
+                }
             }
         }
         println!();
@@ -1,5 +1,6 @@
+use crate::commands::common::server_address;
 use crate::utils::display::print_unicode_box;
-use crate::utils::server::stop_server;
+use crate::utils::server::{is_local_host, stop_server};
 use clap::{Arg, ArgAction, ArgMatches, Command};
 use colored::*;
 use std::process;
@@ -11,8 +12,8 @@ pub fn command() -> Command {
             Arg::new("port")
                 .short('p')
                 .long("port")
-                .help("Port the server is running on")
-                .default_value("5444")
+                .help("Port the server is running on, overrides --server-port")
+                .value_parser(clap::value_parser!(u16))
                 .action(ArgAction::Set),
         )
 }
@@ -20,11 +21,19 @@ pub fn command() -> Command {
 pub fn execute(matches: &ArgMatches) {
     print_unicode_box("🛑 Stopping stackql server...");
 
+    let (server_host, server_port) = server_address(matches);
     let port = matches
-        .get_one::<String>("port")
-        .unwrap_or(&"5444".to_string())
-        .parse::<u16>()
-        .unwrap_or(5444);
+        .get_one::<u16>("port")
+        .copied()
+        .unwrap_or(server_port);
+
+    if !is_local_host(&server_host) {
+        eprintln!(
+            "{}",
+            format!("Cannot stop a server on remote host {}", server_host).red()
+        );
+        process::exit(1);
+    }
 
     match stop_server(port) {
         Ok(_) => {
@@ -1,18 +1,261 @@
-use crate::utils::display::print_unicode_box;
+use crate::commands::common::{
+    dry_run_arg, env_file_arg, env_var_arg, parallelism_arg, server_address, stack_context,
+};
+use crate::commands::report::{CommandReport, ResourceAction, ResourceReport};
+use crate::resource::dry_run::print_rendered_queries;
+use crate::resource::manifest::{Manifest, Resource};
+use crate::resource::operation::{
+    capture_exports, check_exists, load_queries, render_query, run_statement_with_retries,
+    wait_for_deletion,
+};
+use crate::resource::query::{Anchor, ResourceQueries};
+use crate::resource::scheduler::{run_scheduled, Direction};
+use crate::template::context::StackContext;
+use crate::utils::display::{
+    print_error, print_info, print_line, print_success, print_unicode_box,
+};
+use crate::utils::query::{SessionPool, StackqlSession};
 use clap::{Arg, ArgMatches, Command};
+use colored::*;
+use serde_json::Value as JsonValue;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Instant;
 
 pub fn command() -> Command {
     Command::new("teardown")
         .about("Teardown a provisioned stack")
         .arg(Arg::new("stack_dir").required(true))
         .arg(Arg::new("stack_env").required(true))
+        .arg(parallelism_arg())
+        .arg(dry_run_arg())
+        .arg(env_file_arg())
+        .arg(env_var_arg())
 }
 
 pub fn execute(matches: &ArgMatches) {
     let stack_dir = matches.get_one::<String>("stack_dir").unwrap();
     let stack_env = matches.get_one::<String>("stack_env").unwrap();
+    let parallelism = *matches.get_one::<u16>("parallelism").unwrap() as usize;
+    let mut report = CommandReport::new("teardown", matches);
+
+    let manifest = match Manifest::load(Path::new(stack_dir)) {
+        Ok(manifest) => manifest,
+        Err(e) => report.fail(&format!("Failed to load manifest: {}", e)),
+    };
+    report.stack = Some(manifest.name.clone());
+
     print_unicode_box(&format!(
         "Tearing down stack: [{}] in environment: [{}]",
-        stack_dir, stack_env
+        manifest.name, stack_env
     ));
+
+    let mut context = match stack_context(matches, &manifest, Path::new(stack_dir), stack_env) {
+        Ok(context) => context,
+        Err(e) => report.fail(&e),
+    };
+
+    if matches.get_flag("dry_run") {
+        let rendered = match print_rendered_queries(
+            &manifest,
+            Path::new(stack_dir),
+            stack_env,
+            &mut context,
+            &[Anchor::Exists, Anchor::Exports, Anchor::Delete],
+            true,
+        ) {
+            Ok(rendered) => rendered,
+            Err(e) => report.fail(&format!("Dry run failed: {}", e)),
+        };
+        let resources = rendered
+            .into_iter()
+            .map(|(name, queries)| ResourceReport::rendered(&name, queries))
+            .collect();
+        report.set_resources(resources, manifest.graph.order());
+        print_success("Dry run complete, no queries were executed");
+        report.succeed();
+        return;
+    }
+
+    let (server_host, server_port) = server_address(matches);
+    let mut session = match StackqlSession::open(&server_host, server_port) {
+        Ok(session) => session,
+        Err(e) => report.fail(&format!("Failed to connect to stackql server: {}", e)),
+    };
+
+    // Resolve exports in deployment order first, so delete queries for dependent
+    // resources can reference identifiers exported by the resources they depend on
+    let mut existing = Vec::new();
+    let mut queries_by_name = BTreeMap::new();
+    let mut exports_by_name = BTreeMap::new();
+    let mut resources = Vec::new();
+    let mut missing = BTreeSet::new();
+    for resource in manifest.ordered_resources() {
+        let started = Instant::now();
+
+        // Queries of a resource whose dependency is gone cannot be rendered without the
+        // dependency's exports, and the resource cannot exist without it
+        let missing_dependency = manifest
+            .graph
+            .dependencies(&resource.name)
+            .map(|(dependency, _)| dependency)
+            .find(|dependency| missing.contains(*dependency));
+        if let Some(dependency) = missing_dependency {
+            print_info(&format!(
+                "[{}] depends on [{}], which does not exist, skipping",
+                resource.name, dependency
+            ));
+            missing.insert(resource.name.clone());
+            resources.push(ResourceReport::new(
+                &resource.name,
+                ResourceAction::NotFound,
+                started,
+            ));
+            continue;
+        }
+
+        match resolve_resource(
+            Path::new(stack_dir),
+            resource,
+            stack_env,
+            &mut context,
+            &mut session,
+        ) {
+            Ok(Some((queries, exports))) => {
+                existing.push(resource.name.clone());
+                queries_by_name.insert(resource.name.clone(), queries);
+                exports_by_name.insert(resource.name.clone(), exports);
+            }
+            Ok(None) => {
+                print_info(&format!("[{}] does not exist, skipping", resource.name));
+                missing.insert(resource.name.clone());
+                resources.push(ResourceReport::new(
+                    &resource.name,
+                    ResourceAction::NotFound,
+                    started,
+                ));
+            }
+            Err(e) => {
+                resources.push(ResourceReport::failed(&resource.name, &e, started));
+                report.set_resources(resources, manifest.graph.order());
+                report.fail(&format!("Failed to resolve [{}]: {}", resource.name, e));
+            }
+        }
+    }
+
+    let sessions = SessionPool::new(session);
+    let resources = Mutex::new(resources);
+
+    // A resource is deleted once every resource that takes exports from it is gone
+    let result = run_scheduled(
+        &manifest.graph,
+        &existing,
+        Direction::Reverse,
+        parallelism,
+        |name| {
+            let resource = manifest.resource(name).unwrap();
+            if parallelism == 1 {
+                print_line(&format!(
+                    "\n{}",
+                    format!("resource: {}", resource.name).bold()
+                ));
+            }
+            let started = Instant::now();
+            let result = sessions.with_session(|session| {
+                delete_resource(
+                    resource,
+                    &queries_by_name[name],
+                    stack_env,
+                    &context,
+                    session,
+                )
+            });
+            let mut resource_report = match &result {
+                Ok(()) => ResourceReport::new(name, ResourceAction::Deleted, started),
+                Err(e) => ResourceReport::failed(name, e, started),
+            };
+            resource_report.exports = exports_by_name[name].clone();
+            resources.lock().unwrap().push(resource_report);
+            result
+        },
+    );
+    report.set_resources(resources.into_inner().unwrap(), manifest.graph.order());
+
+    if let Err(failures) = result {
+        let names: Vec<String> = failures.iter().map(|(name, _)| name.clone()).collect();
+        for (name, e) in failures {
+            print_error(&format!("Failed to delete [{}]: {}", name, e));
+        }
+        report.exit_failed(&format!("Failed to delete [{}]", names.join("], [")));
+    }
+
+    print_success(&format!("Stack [{}] torn down successfully", manifest.name));
+    report.succeed();
+}
+
+/// Queries and exports of a resource that exists
+type ResolvedResource = (ResourceQueries, BTreeMap<String, JsonValue>);
+
+/// Check whether a resource exists and capture its exports into the context,
+/// returns the resource's queries and exports if it exists
+fn resolve_resource(
+    stack_dir: &Path,
+    resource: &Resource,
+    stack_env: &str,
+    context: &mut StackContext,
+    session: &mut StackqlSession,
+) -> Result<Option<ResolvedResource>, String> {
+    let queries = load_queries(stack_dir, resource)?;
+    let resource_context = context.with_resource(resource, stack_env)?;
+
+    if let Some(query) = queries.get(Anchor::Exists) {
+        print_info(&format!("checking if [{}] exists...", resource.name));
+        if !check_exists(&render_query(resource, query, &resource_context)?, session)? {
+            return Ok(None);
+        }
+    }
+
+    let exports = capture_exports(resource, &queries, &resource_context, session)?;
+    context.insert_exports(&exports);
+
+    Ok(Some((queries, exports)))
+}
+
+/// Delete a resource and wait until its `exists` query no longer finds it
+fn delete_resource(
+    resource: &Resource,
+    queries: &ResourceQueries,
+    stack_env: &str,
+    context: &StackContext,
+    session: &mut StackqlSession,
+) -> Result<(), String> {
+    let resource_context = context.with_resource(resource, stack_env)?;
+
+    let delete = queries
+        .get(Anchor::Delete)
+        .ok_or_else(|| "no 'delete' query is defined".to_string())?;
+    print_info(&format!("deleting [{}]...", resource.name));
+    run_statement_with_retries(
+        &render_query(resource, delete, &resource_context)?,
+        &delete.options,
+        session,
+    )?;
+
+    if let Some(query) = queries.get(Anchor::Exists) {
+        print_info(&format!(
+            "verifying [{}] has been deleted...",
+            resource.name
+        ));
+        let sql = render_query(resource, query, &resource_context)?;
+        if !wait_for_deletion(&sql, &delete.options, session)? {
+            return Err(format!(
+                "resource still exists after {} checks",
+                delete.options.postdelete_retries.max(1)
+            ));
+        }
+    }
+
+    print_success(&format!("[{}] deleted", resource.name));
+    Ok(())
 }
@@ -1,18 +1,195 @@
-use crate::utils::display::print_unicode_box;
+use crate::commands::common::{
+    env_file_arg, env_var_arg, output_file, output_file_arg, server_address, stack_context,
+};
+use crate::commands::report::{CommandReport, ResourceAction, ResourceReport};
+use crate::resource::exports::{stack_exports, write_exports};
+use crate::resource::manifest::{Manifest, Resource};
+use crate::resource::operation::{
+    capture_exports, check_exists, check_state, load_queries, render_query,
+};
+use crate::resource::query::{Anchor, QueryOptions};
+use crate::template::context::StackContext;
+use crate::utils::display::{print_info, print_line, print_unicode_box};
+use crate::utils::query::StackqlSession;
 use clap::{Arg, ArgMatches, Command};
+use colored::*;
+use serde_json::Value as JsonValue;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use std::time::Instant;
 
 pub fn command() -> Command {
     Command::new("test")
         .about("Run test queries for the stack")
         .arg(Arg::new("stack_dir").required(true))
         .arg(Arg::new("stack_env").required(true))
+        .arg(output_file_arg())
+        .arg(env_file_arg())
+        .arg(env_var_arg())
 }
 
 pub fn execute(matches: &ArgMatches) {
     let stack_dir = matches.get_one::<String>("stack_dir").unwrap();
     let stack_env = matches.get_one::<String>("stack_env").unwrap();
+    let mut report = CommandReport::new("test", matches);
+    let output_file = match output_file(matches) {
+        Ok(output_file) => output_file,
+        Err(e) => report.fail(&format!("Invalid --output-file: {}", e)),
+    };
+
+    let manifest = match Manifest::load(Path::new(stack_dir)) {
+        Ok(manifest) => manifest,
+        Err(e) => report.fail(&format!("Failed to load manifest: {}", e)),
+    };
+    report.stack = Some(manifest.name.clone());
+
     print_unicode_box(&format!(
         "Testing stack: [{}] in environment: [{}]",
-        stack_dir, stack_env
+        manifest.name, stack_env
     ));
+
+    let mut context = match stack_context(matches, &manifest, Path::new(stack_dir), stack_env) {
+        Ok(context) => context,
+        Err(e) => report.fail(&e),
+    };
+
+    let (server_host, server_port) = server_address(matches);
+    let mut session = match StackqlSession::open(&server_host, server_port) {
+        Ok(session) => session,
+        Err(e) => report.fail(&format!("Failed to connect to stackql server: {}", e)),
+    };
+
+    let mut results = Vec::new();
+    let mut failed_names = BTreeSet::new();
+    for resource in manifest.ordered_resources() {
+        let started = Instant::now();
+
+        // Queries of a resource whose dependency failed cannot be rendered without the
+        // dependency's exports
+        let failed_dependency = manifest
+            .graph
+            .dependencies(&resource.name)
+            .map(|(dependency, _)| dependency)
+            .find(|dependency| failed_names.contains(*dependency));
+        if let Some(dependency) = failed_dependency {
+            print_info(&format!(
+                "[{}] depends on [{}], which failed, skipping",
+                resource.name, dependency
+            ));
+            let mut result = ResourceReport::new(&resource.name, ResourceAction::NotRun, started);
+            result.message = Some(format!("depends on [{}], which failed", dependency));
+            failed_names.insert(resource.name.clone());
+            results.push(result);
+            continue;
+        }
+
+        let result = match test_resource(
+            Path::new(stack_dir),
+            resource,
+            stack_env,
+            &mut context,
+            &mut session,
+        ) {
+            Ok((detail, exports)) => {
+                let mut result =
+                    ResourceReport::new(&resource.name, ResourceAction::Passed, started);
+                result.message = Some(detail);
+                result.exports = exports;
+                result
+            }
+            Err(e) => {
+                failed_names.insert(resource.name.clone());
+                ResourceReport::failed(&resource.name, &e, started)
+            }
+        };
+        results.push(result);
+    }
+
+    print_line(&format!("\n{}", "Test results".bold()));
+    for result in &results {
+        let (status, detail) = match (result.action, &result.message, &result.error) {
+            (_, _, Some(error)) => ("FAIL".red(), error),
+            (ResourceAction::NotRun, Some(message), _) => ("SKIP".yellow(), message),
+            (_, Some(message), _) => ("PASS".green(), message),
+            _ => continue,
+        };
+        print_line(&format!("  [{}] {}: {}", status, result.name, detail));
+    }
+
+    let total = results.len();
+    let failed = results
+        .iter()
+        .filter(|result| result.action == ResourceAction::Failed)
+        .count();
+    let not_run = results
+        .iter()
+        .filter(|result| result.action == ResourceAction::NotRun)
+        .count();
+    report.resources = results;
+    if failed > 0 {
+        let mut message = format!(
+            "{} of {} resources do not match the manifest",
+            failed, total
+        );
+        if not_run > 0 {
+            message.push_str(&format!(", {} could not be tested", not_run));
+        }
+        report.fail(&message);
+    }
+
+    if let Some(output_file) = output_file {
+        let exports = stack_exports(&context);
+        if let Err(e) = write_exports(&output_file, &exports) {
+            report.fail(&format!("Failed to write exports: {}", e));
+        }
+        print_info(&format!(
+            "Wrote {} exports to {}",
+            exports.len(),
+            output_file.display()
+        ));
+    }
+
+    print_line(
+        &format!("All {} resources match the manifest", total)
+            .green()
+            .to_string(),
+    );
+    report.succeed();
+}
+
+/// Check a resource against its `statecheck` (or `exists`) query without changing it,
+/// then capture its exports for the resources that follow, returns what passed and the exports
+fn test_resource(
+    stack_dir: &Path,
+    resource: &Resource,
+    stack_env: &str,
+    context: &mut StackContext,
+    session: &mut StackqlSession,
+) -> Result<(String, BTreeMap<String, JsonValue>), String> {
+    let queries = load_queries(stack_dir, resource)?;
+    let resource_context = context.with_resource(resource, stack_env)?;
+
+    print_info(&format!("testing [{}]...", resource.name));
+
+    // Drift is reported right away, the anchor's retries only wait out provisioning
+    let detail = if let Some(query) = queries.get(Anchor::Statecheck) {
+        let sql = render_query(resource, query, &resource_context)?;
+        if !check_state(&sql, &QueryOptions::default(), session)? {
+            return Err("statecheck failed, resource has drifted from the manifest".to_string());
+        }
+        "statecheck passed"
+    } else if let Some(query) = queries.get(Anchor::Exists) {
+        let sql = render_query(resource, query, &resource_context)?;
+        if !check_exists(&sql, session)? {
+            return Err("resource does not exist".to_string());
+        }
+        "exists check passed (no statecheck defined)"
+    } else {
+        return Err("no 'statecheck' or 'exists' query is defined".to_string());
+    };
+
+    let exports = capture_exports(resource, &queries, &resource_context, session)?;
+    context.insert_exports(&exports);
+
+    Ok((detail.to_string(), exports))
 }
@@ -1,7 +1,9 @@
+use crate::utils::binary::get_binary_path;
+use crate::utils::display::print_info;
 use crate::utils::display::print_unicode_box;
-use crate::utils::download::download_binary;
+use crate::utils::download::upgrade_binary;
 use crate::utils::stackql::get_version;
-use clap::Command;
+use clap::{ArgMatches, Command};
 use colored::*;
 use std::process;
 
@@ -9,12 +11,23 @@ pub fn command() -> Command {
     Command::new("upgrade").about("Upgrade stackql to the latest version")
 }
 
-pub fn execute() {
+pub fn execute(matches: &ArgMatches) {
     print_unicode_box("📦 Upgrading stackql...");
 
+    if matches.get_flag("offline") {
+        eprintln!("{}", "Cannot upgrade stackql with --offline".red());
+        process::exit(1);
+    }
+
     // Download the latest version of stackql binary
-    match download_binary() {
+    match upgrade_binary() {
         Ok(path) => {
+            if let Some(used) = get_binary_path().filter(|used| *used != path) {
+                print_info(&format!(
+                    "stackql at {} is found before the upgraded binary and will still be used",
+                    used.display()
+                ));
+            }
             // Get the version of the newly installed binary
             match get_version() {
                 Ok(version_info) => {
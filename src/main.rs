@@ -1,13 +1,44 @@
 mod commands;
 mod error;
+mod resource;
+mod template;
+#[cfg(test)]
+mod test_support;
 mod utils;
 
-use crate::utils::display::{print_error, print_info};
-use crate::utils::server::stop_server;
-use clap::Command;
+use crate::commands::common::{
+    keep_server_arg, offline_arg, output_arg, pinned_stackql_version, server_address,
+    server_host_arg, server_port_arg, stackql_version_arg,
+};
+use crate::commands::report::CommandReport;
+use crate::utils::binary::{pinned_binary_path, select_binary};
+use crate::utils::display::{print_error, print_info, print_line, set_json_output};
+use crate::utils::download::{download_binary, download_version};
+use crate::utils::server::{
+    is_local_host, is_server_running, set_keep_started_server, stop_started_server,
+};
+use clap::{ArgMatches, Command};
+use colored::*;
 use error::{get_binary_path_with_error, AppError};
 use std::process;
 
+/// Commands that print a JSON document with `--output json`
+const REPORTING_COMMANDS: [&str; 5] = ["info", "build", "test", "plan", "teardown"];
+
+/// Exit on an error raised before the command runs, still emitting the command's
+/// JSON document when one is expected
+fn exit_with_error(matches: &ArgMatches, message: &str) -> ! {
+    match matches.subcommand() {
+        Some((name, sub_matches)) if REPORTING_COMMANDS.contains(&name) => {
+            CommandReport::new(name, sub_matches).fail(message)
+        }
+        _ => {
+            print_error(message);
+            process::exit(1)
+        }
+    }
+}
+
 fn main() {
     let matches = Command::new("stackql-deploy")
         .version("0.1.0")
@@ -15,6 +46,12 @@ fn main() {
         .about("Model driven IaC using stackql")
         .subcommand_required(true)
         .arg_required_else_help(true)
+        .arg(server_host_arg())
+        .arg(server_port_arg())
+        .arg(keep_server_arg())
+        .arg(offline_arg())
+        .arg(stackql_version_arg())
+        .arg(output_arg())
         .subcommand(commands::build::command())
         .subcommand(commands::teardown::command())
         .subcommand(commands::test::command())
@@ -27,57 +64,111 @@ fn main() {
         .subcommand(commands::plan::command())
         .get_matches();
 
-    // Check for binary existence except for init and server management commands
-    let exempt_commands = ["init"];
-    if !exempt_commands.contains(&matches.subcommand_name().unwrap_or("")) {
-        if let Err(AppError::BinaryNotFound) = get_binary_path_with_error() {
-            print_info("stackql binary not found in the current directory or in the PATH. Downloading the latest version...");
-            // Call your download code here
-            process::exit(1);
+    // With JSON output stdout carries only the command's document
+    set_json_output(matches.get_one::<String>("output").map(String::as_str) == Some("json"));
+
+    // Dry runs only render queries and `plan --graph` only reads the manifest, so they
+    // need neither the binary nor a server
+    let dry_run = matches
+        .subcommand()
+        .and_then(|(_, sub_matches)| sub_matches.try_get_one::<bool>("dry_run").ok().flatten())
+        .copied()
+        .unwrap_or(false);
+    let graph_only = matches
+        .subcommand()
+        .and_then(|(_, sub_matches)| sub_matches.try_get_one::<String>("graph").ok().flatten())
+        .is_some();
+
+    // A pinned release is used from the download cache, info only reports on it
+    let pinned_version = match matches.subcommand().map(|(_, m)| pinned_stackql_version(m)) {
+        Some(Ok(pinned)) if matches.subcommand_name() != Some("info") => pinned,
+        Some(Err(e)) => exit_with_error(&matches, &e),
+        _ => None,
+    };
+
+    // Check for binary existence except for commands that don't run it, download it if missing
+    let exempt_commands = ["init", "upgrade", "stop-server"];
+    if !dry_run
+        && !graph_only
+        && !exempt_commands.contains(&matches.subcommand_name().unwrap_or(""))
+    {
+        match pinned_version {
+            Some((version, source)) => {
+                let binary_path = pinned_binary_path(&version);
+                if !binary_path.is_file() {
+                    if matches.get_flag("offline") {
+                        exit_with_error(&matches, &format!("stackql {} (pinned by {}) is not in the download cache, and --offline prevents downloading it.", version, source));
+                    }
+                    print_info(&format!(
+                        "stackql {} (pinned by {}) is not in the download cache. Downloading it...",
+                        version, source
+                    ));
+                    if let Err(e) = download_version(&version) {
+                        exit_with_error(
+                            &matches,
+                            &format!("Failed to download stackql {}: {}", version, e),
+                        );
+                    }
+                }
+                select_binary(binary_path);
+
+                // The pin only applies to a server this command starts, one that is already
+                // running is used whatever its version
+                let (host, port) = matches
+                    .subcommand()
+                    .map(|(_, sub_matches)| server_address(sub_matches))
+                    .unwrap_or_default();
+                if !is_local_host(&host) || is_server_running(port) {
+                    print_line(
+                        &format!(
+                            "Using the stackql server already running on {}:{}, it may not be stackql {} (pinned by {})",
+                            host, port, version, source
+                        )
+                        .yellow()
+                        .to_string(),
+                    );
+                }
+            }
+            _none => {
+                if let Err(AppError::BinaryNotFound) = get_binary_path_with_error() {
+                    if matches.get_flag("offline") {
+                        exit_with_error(&matches, "stackql binary not found in the current directory, the download cache or the PATH, and --offline prevents downloading it.");
+                    }
+                    print_info("stackql binary not found in the current directory, the download cache or the PATH. Downloading the latest version...");
+                    if let Err(e) = download_binary() {
+                        exit_with_error(&matches, &format!("Failed to download stackql: {}", e));
+                    }
+                }
+            }
         }
-        // if let None = get_binary_path() {
-        //     print_info("stackql binary not found in the current directory or in the PATH. Downloading the latest version...");
-        //     // Call your download code here
-        //     process::exit(1);
-        // }
     }
 
-    // Define which commands need server management
-    let server_commands = ["build", "test", "plan", "teardown", "shell"];
-    let needs_server = server_commands.contains(&matches.subcommand_name().unwrap_or(""));
-    let default_port = 5444;
+    // Commands stop a server they started when they finish, unless asked to keep it
+    set_keep_started_server(matches.get_flag("keep_server"));
 
     // Handle command execution
     match matches.subcommand() {
         Some(("build", sub_matches)) => {
             commands::build::execute(sub_matches);
-            if needs_server {
-                stop_server(default_port).ok();
-            }
+            stop_started_server().ok();
         }
         Some(("teardown", sub_matches)) => {
             commands::teardown::execute(sub_matches);
-            if needs_server {
-                stop_server(default_port).ok();
-            }
+            stop_started_server().ok();
         }
         Some(("test", sub_matches)) => {
             commands::test::execute(sub_matches);
-            if needs_server {
-                stop_server(default_port).ok();
-            }
+            stop_started_server().ok();
         }
-        Some(("info", _)) => commands::info::execute(),
+        Some(("info", sub_matches)) => commands::info::execute(sub_matches),
         Some(("shell", sub_matches)) => commands::shell::execute(sub_matches),
-        Some(("upgrade", _)) => commands::upgrade::execute(),
+        Some(("upgrade", sub_matches)) => commands::upgrade::execute(sub_matches),
         Some(("init", sub_matches)) => commands::init::execute(sub_matches),
         Some(("start-server", sub_matches)) => commands::start_server::execute(sub_matches),
         Some(("stop-server", sub_matches)) => commands::stop_server::execute(sub_matches),
-        Some(("plan", _)) => {
-            commands::plan::execute();
-            if needs_server {
-                stop_server(default_port).ok();
-            }
+        Some(("plan", sub_matches)) => {
+            commands::plan::execute(sub_matches);
+            stop_started_server().ok();
         }
         _ => {
             print_error("Unknown command. Use --help for usage.");
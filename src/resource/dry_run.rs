@@ -0,0 +1,91 @@
+use crate::resource::manifest::{Manifest, Resource};
+use crate::resource::operation::{load_queries, render_query, value_to_string, UNKNOWN_EXPORT};
+use crate::resource::query::{Anchor, ResourceQueries};
+use crate::template::context::{Layer, StackContext};
+use crate::utils::display::print_line;
+use colored::*;
+use serde_json::Value as JsonValue;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Queries rendered for a resource, by anchor name
+pub type RenderedQueries = BTreeMap<String, String>;
+
+/// Render the queries a command would run against each resource and print them alongside
+/// the values they were rendered with, without executing anything. Exports are replaced by
+/// a placeholder, so queries that use them render the way they would before a first build.
+/// Returns the rendered queries of each resource, in the order they were printed.
+pub fn print_rendered_queries(
+    manifest: &Manifest,
+    stack_dir: &Path,
+    stack_env: &str,
+    context: &mut StackContext,
+    anchors: &[Anchor],
+    reverse: bool,
+) -> Result<Vec<(String, RenderedQueries)>, String> {
+    print_values("imports", context.layer(Layer::Import));
+    print_values("globals", context.layer(Layer::Global));
+
+    let mut rendered: Vec<(&Resource, ResourceQueries, StackContext)> = Vec::new();
+    for resource in manifest.ordered_resources() {
+        let queries = load_queries(stack_dir, resource)?;
+        let resource_context = context.with_resource(resource, stack_env)?;
+        for name in &resource.exports {
+            context.insert_export(name, JsonValue::String(UNKNOWN_EXPORT.to_string()));
+        }
+        rendered.push((resource, queries, resource_context));
+    }
+
+    // Teardown resolves every export before deleting, so delete queries see them all
+    if reverse {
+        rendered.reverse();
+        for (resource, _, resource_context) in rendered.iter_mut() {
+            *resource_context = context.with_resource(resource, stack_env)?;
+        }
+    }
+
+    let mut rendered_queries = Vec::new();
+    for (resource, queries, resource_context) in &rendered {
+        print_line(&format!(
+            "\n{}",
+            format!("resource: {}", resource.name).bold()
+        ));
+        print_values("props", resource_context.layer(Layer::Prop));
+        print_values(
+            "exports",
+            resource_context.layer(Layer::Export).filter(|(name, _)| {
+                manifest
+                    .graph
+                    .dependencies(&resource.name)
+                    .any(|(_, vars)| vars.contains(*name))
+            }),
+        );
+
+        let mut resource_queries = RenderedQueries::new();
+        for anchor in anchors {
+            if let Some(query) = queries.get(*anchor) {
+                print_line(&format!("  {}", format!("/*+ {} */", anchor).cyan()));
+                let sql = render_query(resource, query, resource_context)?;
+                for line in sql.lines() {
+                    print_line(&format!("    {}", line.dimmed()));
+                }
+                resource_queries.insert(anchor.to_string(), sql);
+            }
+        }
+        rendered_queries.push((resource.name.clone(), resource_queries));
+    }
+
+    Ok(rendered_queries)
+}
+
+fn print_values<'a>(label: &str, values: impl Iterator<Item = (&'a String, &'a JsonValue)>) {
+    let values: Vec<String> = values
+        .map(|(name, value)| format!("{} = {}", name, value_to_string(value)))
+        .collect();
+    if !values.is_empty() {
+        print_line(&format!("  {}:", label));
+        for value in values {
+            print_line(&format!("    {}", value));
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use crate::resource::operation::value_to_string;
+use crate::template::context::{Layer, StackContext};
+use crate::utils::env::{format_env_file, load_env_file};
+use serde_json::Value as JsonValue;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// Format of a stack exports file, chosen by its file name
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportsFormat {
+    /// A JSON object of export names to their values, e.g. `exports.json`
+    Json,
+    /// `NAME=value` lines, e.g. `exports.env` or `.env.dev`
+    Dotenv,
+}
+
+impl ExportsFormat {
+    pub fn from_path(path: &Path) -> Result<Self, String> {
+        let file_name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        match path.extension().map(|ext| ext.to_string_lossy().to_lowercase()) {
+            Some(ext) if ext == "json" => Ok(ExportsFormat::Json),
+            Some(ext) if ext == "env" => Ok(ExportsFormat::Dotenv),
+            _ if file_name.starts_with(".env") => Ok(ExportsFormat::Dotenv),
+            _ => Err(format!(
+                "cannot tell the format of '{}', use a .json file for JSON or a .env file for dotenv",
+                path.display()
+            )),
+        }
+    }
+}
+
+/// Exports captured into the context so far
+pub fn stack_exports(context: &StackContext) -> BTreeMap<String, JsonValue> {
+    context
+        .layer(Layer::Export)
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect()
+}
+
+/// Write exports to a file in the format its name calls for
+pub fn write_exports(path: &Path, exports: &BTreeMap<String, JsonValue>) -> Result<(), String> {
+    let content = match ExportsFormat::from_path(path)? {
+        ExportsFormat::Json => {
+            let mut json = serde_json::to_string_pretty(exports)
+                .map_err(|e| format!("Failed to serialize exports: {}", e))?;
+            json.push('\n');
+            json
+        }
+        ExportsFormat::Dotenv => {
+            // NULL exports are written as empty values
+            let vars = exports
+                .iter()
+                .map(|(name, value)| match value {
+                    JsonValue::Null => (name.clone(), String::new()),
+                    _ => (name.clone(), value_to_string(value)),
+                })
+                .collect();
+            format_env_file(&vars)
+        }
+    };
+
+    fs::write(path, content).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+/// Read an exports file written by `write_exports`, dotenv values are read as strings
+pub fn read_exports(path: &Path) -> Result<BTreeMap<String, JsonValue>, String> {
+    match ExportsFormat::from_path(path)? {
+        ExportsFormat::Json => {
+            let content = fs::read_to_string(path)
+                .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+            serde_json::from_str(&content).map_err(|e| {
+                format!(
+                    "{}: expected a JSON object of exports: {}",
+                    path.display(),
+                    e
+                )
+            })
+        }
+        ExportsFormat::Dotenv => Ok(load_env_file(path)?
+            .into_iter()
+            .map(|(name, value)| (name, JsonValue::String(value)))
+            .collect()),
+    }
+}
@@ -0,0 +1,299 @@
+use crate::resource::manifest::{Manifest, Resource};
+use crate::resource::operation::load_queries;
+use crate::resource::query::Anchor;
+use crate::template::engine::referenced_variables;
+use serde_yaml::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+/// Dependencies between a stack's resources, inferred from the exports each one references
+#[derive(Debug, Default)]
+pub struct DependencyGraph {
+    /// Resource names in manifest order
+    names: Vec<String>,
+    /// resource -> resource it depends on -> variables that create the dependency
+    dependencies: BTreeMap<String, BTreeMap<String, BTreeSet<String>>>,
+    /// Resource names in deployment order
+    order: Vec<String>,
+}
+
+impl DependencyGraph {
+    /// Scan every resource's queries and props for variables exported by other resources
+    pub fn build(manifest: &Manifest, stack_dir: &Path) -> Result<Self, String> {
+        let names: Vec<String> = manifest.resources.iter().map(|r| r.name.clone()).collect();
+
+        let mut dependencies = BTreeMap::new();
+        for (position, resource) in manifest.resources.iter().enumerate() {
+            let mut resource_dependencies: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+            for variable in referenced_by(resource, stack_dir)? {
+                if let Some(exporter) = find_exporter(manifest, position, &variable) {
+                    resource_dependencies
+                        .entry(exporter.to_string())
+                        .or_default()
+                        .insert(variable);
+                }
+            }
+            dependencies.insert(resource.name.clone(), resource_dependencies);
+        }
+
+        let mut graph = Self {
+            names,
+            dependencies,
+            order: Vec::new(),
+        };
+        graph.order = graph.topological_order()?;
+        Ok(graph)
+    }
+
+    /// Resource names in an order where every resource follows its dependencies
+    pub fn order(&self) -> &[String] {
+        &self.order
+    }
+
+    /// Resources a resource depends on, with the variables that create each dependency
+    pub fn dependencies(&self, name: &str) -> impl Iterator<Item = (&String, &BTreeSet<String>)> {
+        self.dependencies.get(name).into_iter().flatten()
+    }
+
+    /// Plain text rendering, one resource per line in deployment order
+    pub fn to_text(&self) -> String {
+        let mut lines = Vec::new();
+        for (index, name) in self.order.iter().enumerate() {
+            let reasons: Vec<String> = self
+                .dependencies(name)
+                .map(|(dependency, variables)| {
+                    format!(
+                        "{} (via {})",
+                        dependency,
+                        variables.iter().cloned().collect::<Vec<_>>().join(", ")
+                    )
+                })
+                .collect();
+
+            if reasons.is_empty() {
+                lines.push(format!("{}. {}", index + 1, name));
+            } else {
+                lines.push(format!(
+                    "{}. {} <- depends on {}",
+                    index + 1,
+                    name,
+                    reasons.join(", ")
+                ));
+            }
+        }
+        lines.join("\n")
+    }
+
+    /// Graphviz DOT rendering, edges point from a dependency to the resources that use it
+    pub fn to_dot(&self, stack_name: &str) -> String {
+        let mut lines = vec![
+            format!("digraph \"{}\" {{", stack_name),
+            "  rankdir=LR;".to_string(),
+        ];
+        for name in &self.order {
+            lines.push(format!("  \"{}\";", name));
+        }
+        for name in &self.order {
+            for (dependency, variables) in self.dependencies(name) {
+                lines.push(format!(
+                    "  \"{}\" -> \"{}\" [label=\"{}\"];",
+                    dependency,
+                    name,
+                    variables.iter().cloned().collect::<Vec<_>>().join(", ")
+                ));
+            }
+        }
+        lines.push("}".to_string());
+        lines.join("\n")
+    }
+
+    /// Kahn's algorithm, preferring manifest order among resources that are ready
+    fn topological_order(&self) -> Result<Vec<String>, String> {
+        let mut remaining: Vec<&String> = self.names.iter().collect();
+        let mut order: Vec<String> = Vec::new();
+
+        while !remaining.is_empty() {
+            let ready = remaining.iter().position(|name| {
+                self.dependencies(name)
+                    .all(|(dependency, _)| order.contains(dependency))
+            });
+
+            match ready {
+                Some(index) => order.push(remaining.remove(index).clone()),
+                _none => {
+                    return Err(format!(
+                        "dependency cycle detected: {}",
+                        self.find_cycle(&remaining)
+                    ))
+                }
+            }
+        }
+
+        Ok(order)
+    }
+
+    /// Describe a cycle among resources that could not be ordered, e.g. "a -> b -> a"
+    fn find_cycle(&self, remaining: &[&String]) -> String {
+        let mut path: Vec<&String> = vec![remaining[0]];
+        loop {
+            let current = path[path.len() - 1];
+            let next = self
+                .dependencies(current)
+                .map(|(dependency, _)| dependency)
+                .find(|dependency| remaining.contains(dependency));
+
+            match next {
+                Some(next) => {
+                    if let Some(start) = path.iter().position(|name| *name == next) {
+                        let mut cycle: Vec<&str> =
+                            path[start..].iter().map(|name| name.as_str()).collect();
+                        cycle.push(next);
+                        return cycle.join(" -> ");
+                    }
+                    path.push(next);
+                }
+                _none => {
+                    return path
+                        .iter()
+                        .map(|name| name.as_str())
+                        .collect::<Vec<_>>()
+                        .join(" -> ")
+                }
+            }
+        }
+    }
+}
+
+/// Variables referenced by a resource's queries, prop values and prop merges
+fn referenced_by(resource: &Resource, stack_dir: &Path) -> Result<BTreeSet<String>, String> {
+    let queries = load_queries(stack_dir, resource)?;
+    let mut variables = BTreeSet::new();
+
+    for anchor in Anchor::ALL {
+        if let Some(query) = queries.get(anchor) {
+            variables.extend(referenced_variables(&query.sql));
+        }
+    }
+
+    for prop in &resource.props {
+        if let Some(value) = &prop.value {
+            collect_value_variables(value, &mut variables);
+        }
+        for env_value in prop.values.iter().flat_map(|values| values.values()) {
+            collect_value_variables(&env_value.value, &mut variables);
+        }
+        variables.extend(prop.merge.iter().cloned());
+    }
+
+    Ok(variables)
+}
+
+fn collect_value_variables(value: &Value, variables: &mut BTreeSet<String>) {
+    match value {
+        Value::String(s) => variables.extend(referenced_variables(s)),
+        Value::Sequence(items) => {
+            for item in items {
+                collect_value_variables(item, variables);
+            }
+        }
+        Value::Mapping(map) => {
+            for item in map.values() {
+                collect_value_variables(item, variables);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// The resource exporting a variable: the nearest one before `position` in the manifest,
+/// otherwise the nearest one after it
+fn find_exporter<'a>(manifest: &'a Manifest, position: usize, variable: &str) -> Option<&'a str> {
+    let exports = |resource: &&Resource| resource.exports.iter().any(|e| e == variable);
+
+    manifest.resources[..position]
+        .iter()
+        .rev()
+        .find(exports)
+        .or_else(|| manifest.resources[position + 1..].iter().find(exports))
+        .map(|resource| resource.name.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::resource::manifest::Manifest;
+    use crate::test_support::temp_stack;
+
+    fn load(manifest: &str, resources: &[(&str, &str)]) -> Result<Manifest, String> {
+        Manifest::load(temp_stack(manifest, resources).path())
+    }
+
+    #[test]
+    fn orders_resources_after_their_dependencies() {
+        let manifest = load(
+            "version: 1\nname: net\nresources:\n  - name: subnet\n  - name: vpc\n    exports: [vpc_id]\n  - name: bucket\n",
+            &[
+                ("subnet", "/*+ create */\nINSERT INTO subnets SELECT '{{ vpc_id }}'"),
+                ("vpc", "/*+ create */\nINSERT INTO vpcs SELECT 1"),
+                ("bucket", "/*+ create */\nINSERT INTO buckets SELECT 1"),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(manifest.graph.order(), ["vpc", "subnet", "bucket"]);
+        let dependencies: Vec<_> = manifest.graph.dependencies("subnet").collect();
+        assert_eq!(dependencies.len(), 1);
+        assert_eq!(dependencies[0].0, "vpc");
+        assert!(dependencies[0].1.contains("vpc_id"));
+    }
+
+    #[test]
+    fn finds_dependencies_in_prop_values() {
+        let manifest = load(
+            "version: 1\nname: net\nresources:\n  - name: vpc\n    exports: [vpc_id]\n  - name: subnet\n    props:\n      - name: tags\n        values:\n          dev:\n            value: [\"{{ vpc_id }}\"]\n",
+            &[
+                ("vpc", "/*+ create */\nINSERT INTO vpcs SELECT 1"),
+                ("subnet", "/*+ create */\nINSERT INTO subnets SELECT '{{ tags }}'"),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(manifest.graph.dependencies("subnet").count(), 1);
+    }
+
+    #[test]
+    fn detects_dependency_cycles() {
+        let error = load(
+            "version: 1\nname: net\nresources:\n  - name: a\n    exports: [a_id]\n  - name: b\n    exports: [b_id]\n  - name: c\n",
+            &[
+                ("a", "/*+ create */\nINSERT INTO a SELECT '{{ b_id }}'"),
+                ("b", "/*+ create */\nINSERT INTO b SELECT '{{ a_id }}'"),
+                ("c", "/*+ create */\nINSERT INTO c SELECT 1"),
+            ],
+        )
+        .unwrap_err();
+
+        assert!(
+            error.ends_with("dependency cycle detected: a -> b -> a"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn renders_the_graph() {
+        let manifest = load(
+            "version: 1\nname: net\nresources:\n  - name: vpc\n    exports: [vpc_id]\n  - name: subnet\n",
+            &[
+                ("vpc", "/*+ create */\nINSERT INTO vpcs SELECT 1"),
+                ("subnet", "/*+ create */\nINSERT INTO subnets SELECT '{{ vpc_id }}'"),
+            ],
+        )
+        .unwrap();
+
+        assert!(manifest
+            .graph
+            .to_dot("net")
+            .contains("\"vpc\" -> \"subnet\""));
+        assert!(manifest.graph.to_text().contains("subnet"));
+    }
+}
@@ -0,0 +1,219 @@
+use crate::resource::exports::read_exports;
+use crate::resource::manifest::{Import, Manifest};
+use crate::resource::operation::{collect_exports, load_queries, render_query, UNKNOWN_EXPORT};
+use crate::resource::query::Anchor;
+use crate::template::context::StackContext;
+use crate::template::engine::render;
+use crate::utils::display::print_info;
+use crate::utils::env::stack_vars;
+use crate::utils::query::StackqlSession;
+use serde_json::Value as JsonValue;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Resolves the `imports` of a stack. Other stacks are rendered with the variables they
+/// would have on their own, the process environment then their own `.env` file, with
+/// only the importing command's `-e` variables on top. A server connection is only
+/// opened when an import has to run another stack's exports queries.
+struct ImportResolver<'a> {
+    /// `-e` variables of the importing command
+    overrides: &'a BTreeMap<String, String>,
+    /// Server to run exports queries on, `None` for dry runs
+    server: Option<(&'a str, u16)>,
+    session: Option<StackqlSession>,
+    /// Stacks whose imports are being resolved, to report import cycles
+    visiting: Vec<PathBuf>,
+}
+
+/// Resolve the exports a stack imports from other stacks, either from their saved exports
+/// files or by running their exports queries. Without a server, as in a dry run, exports
+/// that would need a query are replaced by a placeholder.
+pub fn resolve_imports(
+    manifest: &Manifest,
+    stack_dir: &Path,
+    stack_env: &str,
+    env_vars: &BTreeMap<String, String>,
+    overrides: &BTreeMap<String, String>,
+    server: Option<(&str, u16)>,
+) -> Result<BTreeMap<String, JsonValue>, String> {
+    if manifest.imports.is_empty() {
+        return Ok(BTreeMap::new());
+    }
+
+    let mut resolver = ImportResolver {
+        overrides,
+        server,
+        session: None,
+        visiting: Vec::new(),
+    };
+    resolver.visit(stack_dir, |resolver| {
+        resolver.resolve(manifest, stack_dir, stack_env, env_vars)
+    })
+}
+
+impl ImportResolver<'_> {
+    /// Run `f` for a stack, failing if the stack is already being resolved
+    fn visit<T>(
+        &mut self,
+        stack_dir: &Path,
+        f: impl FnOnce(&mut Self) -> Result<T, String>,
+    ) -> Result<T, String> {
+        let canonical = stack_dir
+            .canonicalize()
+            .map_err(|e| format!("Failed to read stack {}: {}", stack_dir.display(), e))?;
+        if self.visiting.contains(&canonical) {
+            return Err(format!(
+                "import cycle, {} imports from itself",
+                stack_dir.display()
+            ));
+        }
+
+        self.visiting.push(canonical);
+        let result = f(self);
+        self.visiting.pop();
+        result
+    }
+
+    fn resolve(
+        &mut self,
+        manifest: &Manifest,
+        stack_dir: &Path,
+        stack_env: &str,
+        env_vars: &BTreeMap<String, String>,
+    ) -> Result<BTreeMap<String, JsonValue>, String> {
+        let mut imported = BTreeMap::new();
+        for import in &manifest.imports {
+            // import paths may refer to the environment imported from, e.g.
+            // `exports-{{ stack_env }}.json`
+            let import_env = import.env.as_deref().unwrap_or(stack_env);
+            let context = StackContext::base(manifest, import_env, env_vars);
+            let (source, values) = match &import.file {
+                Some(file) => {
+                    let path = stack_dir.join(render(file, &context, "import file")?);
+                    print_info(&format!(
+                        "importing [{}] from {}",
+                        import.exports.join(", "),
+                        path.display()
+                    ));
+                    (path.display().to_string(), read_exports(&path)?)
+                }
+                _none => {
+                    let stack = render(import.stack.as_deref().unwrap(), &context, "import stack")?;
+                    let other_dir = stack_dir.join(&stack);
+                    print_info(&format!(
+                        "importing [{}] from stack {} ({})",
+                        import.exports.join(", "),
+                        other_dir.display(),
+                        import_env
+                    ));
+                    let values = self.visit(&other_dir, |resolver| {
+                        resolver.query_exports(&other_dir, import_env, import)
+                    })?;
+                    (other_dir.display().to_string(), values)
+                }
+            };
+
+            for name in &import.exports {
+                let value = values
+                    .get(name)
+                    .ok_or_else(|| format!("{} does not export '{}'", source, name))?;
+                imported.insert(name.clone(), value.clone());
+            }
+        }
+
+        Ok(imported)
+    }
+
+    /// Run the exports queries of another stack, in deployment order, until every
+    /// export the import lists has been captured
+    fn query_exports(
+        &mut self,
+        stack_dir: &Path,
+        stack_env: &str,
+        import: &Import,
+    ) -> Result<BTreeMap<String, JsonValue>, String> {
+        let manifest = Manifest::load(stack_dir)?;
+        let env_vars = stack_vars(stack_dir, None, self.overrides)?;
+        let imports = self.resolve(&manifest, stack_dir, stack_env, &env_vars)?;
+        let mut context = StackContext::for_stack(&manifest, stack_env, &env_vars, &imports)?;
+
+        let mut exports = BTreeMap::new();
+        for resource in manifest.ordered_resources() {
+            if import.exports.iter().all(|name| exports.contains_key(name)) {
+                break;
+            }
+            if resource.exports.is_empty() {
+                continue;
+            }
+
+            let (host, port) = match self.server {
+                Some(server) => server,
+                _none => {
+                    for name in &resource.exports {
+                        let placeholder = JsonValue::String(UNKNOWN_EXPORT.to_string());
+                        context.insert_export(name, placeholder.clone());
+                        exports.insert(name.clone(), placeholder);
+                    }
+                    continue;
+                }
+            };
+
+            let queries = load_queries(stack_dir, resource)?;
+            let query = match queries.get(Anchor::Exports) {
+                Some(query) => query,
+                _none => continue,
+            };
+            let resource_context = context.with_resource(resource, stack_env)?;
+            let sql = render_query(resource, query, &resource_context)?;
+
+            if self.session.is_none() {
+                self.session = Some(StackqlSession::open(host, port)?);
+            }
+            let session = self.session.as_mut().unwrap();
+            let values = collect_exports(resource, &sql, &query.options, session).map_err(|e| {
+                format!(
+                    "stack [{}] resource [{}]: {}",
+                    manifest.name, resource.name, e
+                )
+            })?;
+            for (name, value) in values {
+                context.insert_export(&name, value.clone());
+                exports.insert(name, value);
+            }
+        }
+
+        Ok(exports)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_stack;
+    use std::fs;
+
+    #[test]
+    fn renders_file_paths_with_the_imported_environment() {
+        let stack_dir = temp_stack(
+            "version: 1\nname: app\nimports:\n  - file: exports-{{ stack_env }}.json\n    env: prd\n    exports: [vpc_id]\nresources: []\n",
+            &[],
+        );
+        fs::write(
+            stack_dir.path().join("exports-prd.json"),
+            "{\"vpc_id\": \"vpc-prd\"}",
+        )
+        .unwrap();
+        let manifest = Manifest::load(stack_dir.path()).unwrap();
+
+        let imported = resolve_imports(
+            &manifest,
+            stack_dir.path(),
+            "dev",
+            &BTreeMap::new(),
+            &BTreeMap::new(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(imported["vpc_id"], "vpc-prd");
+    }
+}
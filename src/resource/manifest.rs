@@ -0,0 +1,374 @@
+use crate::resource::graph::DependencyGraph;
+use crate::utils::stackql::normalize_version;
+use serde::Deserialize;
+use serde_yaml::Value;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// File name of the manifest expected at the root of every stack directory
+pub const MANIFEST_FILE_NAME: &str = "stackql_manifest.yml";
+
+/// Manifest versions understood by this release
+const SUPPORTED_VERSIONS: [u32; 1] = [1];
+
+/// Top level `stackql_manifest.yml` document
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    pub version: u32,
+    pub name: String,
+    #[serde(default)]
+    #[allow(dead_code)]
+    pub description: Option<String>,
+    /// stackql release the stack is deployed with, e.g. `v0.5.612`
+    #[serde(default)]
+    pub stackql_version: Option<String>,
+    #[serde(default)]
+    pub providers: Vec<String>,
+    #[serde(default)]
+    pub imports: Vec<Import>,
+    #[serde(default)]
+    pub globals: Vec<Global>,
+    #[serde(default)]
+    pub resources: Vec<Resource>,
+    /// Dependencies between resources, inferred when the manifest is loaded
+    #[serde(skip)]
+    pub graph: DependencyGraph,
+}
+
+/// Exports taken from another stack, available to the globals and every resource
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Import {
+    /// Directory of the other stack, relative to this stack directory
+    #[serde(default)]
+    pub stack: Option<String>,
+    /// Environment of the other stack, defaults to the environment being deployed.
+    /// `stack` and `file` paths are rendered with it as `stack_env`.
+    #[serde(default)]
+    pub env: Option<String>,
+    /// Exports file saved by `build --output-file`, relative to this stack directory,
+    /// read instead of running the other stack's exports queries
+    #[serde(default)]
+    pub file: Option<String>,
+    pub exports: Vec<String>,
+}
+
+/// A stack wide variable available to every resource
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Global {
+    pub name: String,
+    #[serde(default)]
+    #[allow(dead_code)]
+    pub description: Option<String>,
+    pub value: Value,
+}
+
+/// A resource provisioned by the stack, backed by `resources/<name>.iql`
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Resource {
+    pub name: String,
+    #[serde(default)]
+    #[allow(dead_code)]
+    pub description: Option<String>,
+    /// Optional query file path relative to the `resources` directory
+    #[serde(default)]
+    pub file: Option<String>,
+    #[serde(default)]
+    pub props: Vec<Property>,
+    #[serde(default)]
+    pub exports: Vec<String>,
+}
+
+/// A resource property, either a single `value` or per environment `values`
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Property {
+    pub name: String,
+    #[serde(default)]
+    #[allow(dead_code)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub value: Option<Value>,
+    #[serde(default)]
+    pub values: Option<BTreeMap<String, EnvValue>>,
+    /// Names of globals or props to merge into this property's value
+    #[serde(default)]
+    pub merge: Vec<String>,
+}
+
+/// The value of a property for a single environment
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EnvValue {
+    pub value: Value,
+}
+
+impl Manifest {
+    /// Load and validate the manifest from a stack directory
+    pub fn load(stack_dir: &Path) -> Result<Self, String> {
+        let manifest_path = stack_dir.join(MANIFEST_FILE_NAME);
+        if !manifest_path.is_file() {
+            return Err(format!(
+                "Manifest file not found: {}",
+                manifest_path.display()
+            ));
+        }
+
+        let content = fs::read_to_string(&manifest_path)
+            .map_err(|e| format!("Failed to read {}: {}", manifest_path.display(), e))?;
+
+        let mut manifest = Self::parse(&content, &manifest_path)?;
+
+        for resource in &manifest.resources {
+            let resource_path = Self::resource_file(stack_dir, resource);
+            if !resource_path.is_file() {
+                return Err(format!(
+                    "{}: query file for resource '{}' not found: {}",
+                    manifest_path.display(),
+                    resource.name,
+                    resource_path.display()
+                ));
+            }
+        }
+
+        manifest.graph = DependencyGraph::build(&manifest, stack_dir)
+            .map_err(|e| format!("{}: {}", manifest_path.display(), e))?;
+
+        Ok(manifest)
+    }
+
+    /// The `stackql_version` pinned by a stack's manifest, read without validating the rest
+    /// of the manifest so the binary can be selected before the command loads it
+    pub fn pinned_stackql_version(stack_dir: &Path) -> Option<String> {
+        let content = fs::read_to_string(stack_dir.join(MANIFEST_FILE_NAME)).ok()?;
+        let document: Value = serde_yaml::from_str(&content).ok()?;
+        document
+            .get("stackql_version")
+            .and_then(Value::as_str)
+            .map(|version| version.to_string())
+    }
+
+    /// Resources in dependency order, every resource follows the resources it depends on
+    pub fn ordered_resources(&self) -> Vec<&Resource> {
+        self.graph
+            .order()
+            .iter()
+            .filter_map(|name| self.resource(name))
+            .collect()
+    }
+
+    /// Look up a resource by name
+    pub fn resource(&self, name: &str) -> Option<&Resource> {
+        self.resources.iter().find(|resource| resource.name == name)
+    }
+
+    /// Parse manifest content, `path` is only used for error reporting
+    pub fn parse(content: &str, path: &Path) -> Result<Self, String> {
+        let manifest: Manifest =
+            serde_yaml::from_str(content).map_err(|e| format_yaml_error(path, &e))?;
+        manifest.validate(path)?;
+        Ok(manifest)
+    }
+
+    /// Path of the query file for a resource within a stack directory
+    pub fn resource_file(stack_dir: &Path, resource: &Resource) -> PathBuf {
+        let file_name = resource
+            .file
+            .clone()
+            .unwrap_or_else(|| format!("{}.iql", resource.name));
+        stack_dir.join("resources").join(file_name)
+    }
+
+    fn validate(&self, path: &Path) -> Result<(), String> {
+        if !SUPPORTED_VERSIONS.contains(&self.version) {
+            return Err(format!(
+                "{}: unsupported manifest version {}, supported versions are: {:?}",
+                path.display(),
+                self.version,
+                SUPPORTED_VERSIONS
+            ));
+        }
+
+        if self.name.trim().is_empty() {
+            return Err(format!("{}: stack name must not be empty", path.display()));
+        }
+
+        if let Some(version) = &self.stackql_version {
+            if normalize_version(version).is_none() {
+                return Err(format!(
+                    "{}: invalid stackql_version '{}', expected a release such as v0.5.612",
+                    path.display(),
+                    version
+                ));
+            }
+        }
+
+        let mut imported_names = HashSet::new();
+        for import in &self.imports {
+            let source = match (&import.file, &import.stack) {
+                (Some(_), Some(_)) => {
+                    return Err(format!(
+                        "{}: an import defines either 'stack' or 'file', not both",
+                        path.display()
+                    ))
+                }
+                (Some(file), _) => file,
+                (_, Some(stack)) => stack,
+                _ => {
+                    return Err(format!(
+                        "{}: every import must define either 'stack' or 'file'",
+                        path.display()
+                    ))
+                }
+            };
+
+            if import.exports.is_empty() {
+                return Err(format!(
+                    "{}: import from '{}' must list the exports it uses",
+                    path.display(),
+                    source
+                ));
+            }
+
+            for name in &import.exports {
+                if !imported_names.insert(name.as_str()) {
+                    return Err(format!(
+                        "{}: '{}' is imported more than once",
+                        path.display(),
+                        name
+                    ));
+                }
+                if let Some(resource) = self
+                    .resources
+                    .iter()
+                    .find(|resource| resource.exports.contains(name))
+                {
+                    return Err(format!(
+                        "{}: '{}' is imported from '{}' and also exported by resource '{}'",
+                        path.display(),
+                        name,
+                        source,
+                        resource.name
+                    ));
+                }
+            }
+        }
+
+        let mut global_names = HashSet::new();
+        for global in &self.globals {
+            if !global_names.insert(global.name.as_str()) {
+                return Err(format!(
+                    "{}: duplicate global '{}'",
+                    path.display(),
+                    global.name
+                ));
+            }
+        }
+
+        let mut resource_names = HashSet::new();
+        for resource in &self.resources {
+            if !resource_names.insert(resource.name.as_str()) {
+                return Err(format!(
+                    "{}: duplicate resource '{}'",
+                    path.display(),
+                    resource.name
+                ));
+            }
+
+            let mut prop_names = HashSet::new();
+            for prop in &resource.props {
+                if !prop_names.insert(prop.name.as_str()) {
+                    return Err(format!(
+                        "{}: duplicate prop '{}' in resource '{}'",
+                        path.display(),
+                        prop.name,
+                        resource.name
+                    ));
+                }
+
+                if prop.value.is_none() && prop.values.is_none() {
+                    return Err(format!(
+                        "{}: prop '{}' in resource '{}' must define either 'value' or 'values'",
+                        path.display(),
+                        prop.name,
+                        resource.name
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Render a YAML error as `file:line:column: message`
+fn format_yaml_error(path: &Path, error: &serde_yaml::Error) -> String {
+    let message = error.to_string();
+    match error.location() {
+        Some(location) => {
+            // serde_yaml appends the location to its message, we report it up front instead
+            let suffix = format!(" at line {} column {}", location.line(), location.column());
+            let message = message.strip_suffix(&suffix).unwrap_or(&message);
+            format!(
+                "{}:{}:{}: {}",
+                path.display(),
+                location.line(),
+                location.column(),
+                message
+            )
+        }
+        _none => format!("{}: {}", path.display(), message),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(content: &str) -> Result<Manifest, String> {
+        Manifest::parse(content, Path::new("stackql_manifest.yml"))
+    }
+
+    #[test]
+    fn accepts_stack_and_file_imports() {
+        let manifest = parse(
+            "version: 1\nname: app\nimports:\n  - stack: ../network\n    env: prd\n    exports: [vpc_id]\n  - file: ../network/exports.json\n    exports: [subnet_id]\nresources:\n  - name: svc\n    exports: [svc_id]\n",
+        )
+        .unwrap();
+        assert_eq!(manifest.imports.len(), 2);
+    }
+
+    #[test]
+    fn rejects_an_import_with_both_stack_and_file() {
+        let error = parse(
+            "version: 1\nname: app\nimports:\n  - stack: ../network\n    file: ../network/exports.json\n    exports: [vpc_id]\n",
+        )
+        .unwrap_err();
+        assert_eq!(
+            error,
+            "stackql_manifest.yml: an import defines either 'stack' or 'file', not both"
+        );
+    }
+
+    #[test]
+    fn rejects_an_import_without_a_source() {
+        let error = parse("version: 1\nname: app\nimports:\n  - exports: [vpc_id]\n").unwrap_err();
+        assert!(error.ends_with("every import must define either 'stack' or 'file'"));
+    }
+
+    #[test]
+    fn rejects_imports_that_collide_with_local_exports() {
+        let error = parse(
+            "version: 1\nname: app\nimports:\n  - stack: ../network\n    exports: [vpc_id]\nresources:\n  - name: vpc\n    exports: [vpc_id]\n",
+        )
+        .unwrap_err();
+        assert_eq!(
+            error,
+            "stackql_manifest.yml: 'vpc_id' is imported from '../network' and also exported by resource 'vpc'"
+        );
+    }
+}
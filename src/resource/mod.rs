@@ -0,0 +1,9 @@
+pub mod dry_run;
+pub mod exports;
+pub mod graph;
+pub mod imports;
+pub mod manifest;
+pub mod operation;
+pub mod props;
+pub mod query;
+pub mod scheduler;
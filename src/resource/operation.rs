@@ -0,0 +1,226 @@
+use crate::resource::manifest::{Manifest, Resource};
+use crate::resource::query::{Anchor, AnchoredQuery, QueryOptions, ResourceQueries};
+use crate::template::context::StackContext;
+use crate::template::engine::render;
+use crate::utils::display::print_info;
+use crate::utils::query::{provider_error, QueryResult, StackqlSession};
+use serde_json::Value as JsonValue;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+/// Placeholder rendered in place of exports that are not known without running queries
+pub const UNKNOWN_EXPORT: &str = "<known after build>";
+
+/// Format a context value the way it is rendered into queries
+pub fn value_to_string(value: &JsonValue) -> String {
+    match value {
+        JsonValue::String(s) => s.clone(),
+        _ => value.to_string(),
+    }
+}
+
+/// Load the anchored queries for a resource in a stack directory
+pub fn load_queries(stack_dir: &Path, resource: &Resource) -> Result<ResourceQueries, String> {
+    ResourceQueries::load(&Manifest::resource_file(stack_dir, resource))
+}
+
+/// Render an anchored query, naming the resource and anchor on failure
+pub fn render_query(
+    resource: &Resource,
+    query: &AnchoredQuery,
+    context: &StackContext,
+) -> Result<String, String> {
+    render(
+        &query.sql,
+        context,
+        &format!("resource '{}' anchor '{}'", resource.name, query.anchor),
+    )
+}
+
+/// Run a query, failing with the provider's message if the server reported a failed
+/// provider request, which would otherwise look like an empty result
+fn run_query(sql: &str, session: &mut StackqlSession) -> Result<QueryResult, String> {
+    let result = session.query(sql)?;
+    match provider_error(&session.notices()) {
+        Some(notice) => Err(format!("provider error: {}", notice)),
+        _none => Ok(result),
+    }
+}
+
+/// Run a `SELECT COUNT(*) as count` style query and return the count
+pub fn query_count(sql: &str, session: &mut StackqlSession) -> Result<u64, String> {
+    match run_query(sql, session)? {
+        QueryResult::Data { columns, rows, .. } => {
+            let index = columns
+                .iter()
+                .position(|column| column.name == "count")
+                .unwrap_or(0);
+            match rows.first().and_then(|row| row.values.get(index)) {
+                Some(Some(JsonValue::Number(count))) => count
+                    .as_u64()
+                    .ok_or_else(|| format!("expected a non-negative integer count, got {}", count)),
+                Some(Some(value)) => Err(format!(
+                    "expected a numeric count, got '{}'",
+                    value_to_string(value)
+                )),
+                Some(None) => Err("expected a numeric count, got NULL".to_string()),
+                _none => Ok(0),
+            }
+        }
+        _ => Err("expected a count, query returned no rows".to_string()),
+    }
+}
+
+/// Run an `exists` query, a count of one means the resource exists
+pub fn check_exists(sql: &str, session: &mut StackqlSession) -> Result<bool, String> {
+    match query_count(sql, session)? {
+        0 => Ok(false),
+        1 => Ok(true),
+        count => Err(format!(
+            "exists query matched {} resources, expected at most one",
+            count
+        )),
+    }
+}
+
+/// Run a `statecheck` query until it reports the desired state or retries are exhausted
+pub fn check_state(
+    sql: &str,
+    options: &QueryOptions,
+    session: &mut StackqlSession,
+) -> Result<bool, String> {
+    let attempts = options.retries.max(1);
+    for attempt in 1..=attempts {
+        if query_count(sql, session)? == 1 {
+            return Ok(true);
+        }
+        if attempt < attempts {
+            print_info(&format!(
+                "state check attempt {}/{} failed, retrying in {} seconds...",
+                attempt, attempts, options.retry_delay
+            ));
+            thread::sleep(Duration::from_secs(options.retry_delay));
+        }
+    }
+    Ok(false)
+}
+
+/// Run an `exists` query after a delete until the resource is gone or retries are exhausted
+pub fn wait_for_deletion(
+    sql: &str,
+    options: &QueryOptions,
+    session: &mut StackqlSession,
+) -> Result<bool, String> {
+    let attempts = options.postdelete_retries.max(1);
+    for attempt in 1..=attempts {
+        if !check_exists(sql, session)? {
+            return Ok(true);
+        }
+        if attempt < attempts {
+            print_info(&format!(
+                "resource still exists (attempt {}/{}), checking again in {} seconds...",
+                attempt, attempts, options.postdelete_retry_delay
+            ));
+            thread::sleep(Duration::from_secs(options.postdelete_retry_delay));
+        }
+    }
+    Ok(false)
+}
+
+/// Run a `create`, `update` or `delete` statement
+pub fn run_statement(sql: &str, session: &mut StackqlSession) -> Result<(), String> {
+    run_query(sql, session).map(|_| ())
+}
+
+/// Run a statement, retrying on failure as configured by the anchor options
+pub fn run_statement_with_retries(
+    sql: &str,
+    options: &QueryOptions,
+    session: &mut StackqlSession,
+) -> Result<(), String> {
+    let attempts = options.retries.max(1);
+    let mut attempt = 1;
+    loop {
+        match run_statement(sql, session) {
+            Ok(()) => return Ok(()),
+            Err(e) if attempt < attempts => {
+                print_info(&format!(
+                    "attempt {}/{} failed ({}), retrying in {} seconds...",
+                    attempt, attempts, e, options.retry_delay
+                ));
+                thread::sleep(Duration::from_secs(options.retry_delay));
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Run a resource's `exports` query when it declares exports, returns the values captured
+pub fn capture_exports(
+    resource: &Resource,
+    queries: &ResourceQueries,
+    resource_context: &StackContext,
+    session: &mut StackqlSession,
+) -> Result<BTreeMap<String, JsonValue>, String> {
+    if resource.exports.is_empty() {
+        return Ok(BTreeMap::new());
+    }
+
+    let query = queries.get(Anchor::Exports).ok_or_else(|| {
+        "exports are declared in the manifest but no 'exports' query is defined".to_string()
+    })?;
+    let sql = render_query(resource, query, resource_context)?;
+    let exports = collect_exports(resource, &sql, &query.options, session)?;
+    for (name, value) in &exports {
+        print_info(&format!("exported [{}] = {}", name, value_to_string(value)));
+    }
+    Ok(exports)
+}
+
+/// Run an `exports` query and return the declared export values
+pub fn collect_exports(
+    resource: &Resource,
+    sql: &str,
+    options: &QueryOptions,
+    session: &mut StackqlSession,
+) -> Result<BTreeMap<String, JsonValue>, String> {
+    let attempts = options.retries.max(1);
+    for attempt in 1..=attempts {
+        if let QueryResult::Data { columns, rows, .. } = run_query(sql, session)? {
+            if rows.len() > 1 {
+                return Err(format!(
+                    "exports query returned {} rows, expected one",
+                    rows.len()
+                ));
+            }
+
+            if let Some(row) = rows.first() {
+                let mut exports = BTreeMap::new();
+                for name in &resource.exports {
+                    let index = columns
+                        .iter()
+                        .position(|column| &column.name == name)
+                        .ok_or_else(|| {
+                            format!("export '{}' not returned by exports query", name)
+                        })?;
+                    let value = row.values[index].clone().unwrap_or(JsonValue::Null);
+                    exports.insert(name.clone(), value);
+                }
+                return Ok(exports);
+            }
+        }
+
+        if attempt < attempts {
+            print_info(&format!(
+                "exports attempt {}/{} returned no rows, retrying in {} seconds...",
+                attempt, attempts, options.retry_delay
+            ));
+            thread::sleep(Duration::from_secs(options.retry_delay));
+        }
+    }
+
+    Err("exports query returned no rows".to_string())
+}
@@ -0,0 +1,161 @@
+use crate::resource::manifest::Property;
+use crate::template::context::StackContext;
+use crate::template::engine::render_value;
+use serde_json::Value as JsonValue;
+
+/// Resolve a prop for the target environment: pick the environment's value (falling back
+/// to `value`), render it, then merge in every global or prop named in `merge`
+pub fn resolve_prop(
+    prop: &Property,
+    stack_env: &str,
+    context: &StackContext,
+    location: &str,
+) -> Result<JsonValue, String> {
+    let value =
+        yaml_to_json(prop_value(prop, stack_env).map_err(|e| format!("{}: {}", location, e))?)?;
+    let mut resolved = render_value(&value, context, location)?;
+
+    for name in &prop.merge {
+        let other = context.get(name).ok_or_else(|| {
+            format!(
+                "{}: cannot merge '{}', it is not a global or an earlier prop",
+                location, name
+            )
+        })?;
+        resolved = merge_values(resolved, other)
+            .map_err(|e| format!("{}: cannot merge '{}': {}", location, name, e))?;
+    }
+
+    Ok(resolved)
+}
+
+/// Pick the value of a prop for an environment, falling back to `value`
+pub fn prop_value<'a>(
+    prop: &'a Property,
+    stack_env: &str,
+) -> Result<&'a serde_yaml::Value, String> {
+    if let Some(env_value) = prop
+        .values
+        .as_ref()
+        .and_then(|values| values.get(stack_env))
+    {
+        return Ok(&env_value.value);
+    }
+
+    if let Some(value) = &prop.value {
+        return Ok(value);
+    }
+
+    let defined: Vec<&str> = prop
+        .values
+        .iter()
+        .flat_map(|values| values.keys().map(String::as_str))
+        .collect();
+    Err(format!(
+        "no value for environment '{}' (values are defined for: {})",
+        stack_env,
+        defined.join(", ")
+    ))
+}
+
+/// Deep merge `other` into `base`: lists are concatenated (skipping items already present),
+/// maps are merged recursively with `base` winning on conflicting scalar keys
+pub fn merge_values(base: JsonValue, other: &JsonValue) -> Result<JsonValue, String> {
+    match (base, other) {
+        (JsonValue::Array(mut items), JsonValue::Array(others)) => {
+            for item in others {
+                if !items.contains(item) {
+                    items.push(item.clone());
+                }
+            }
+            Ok(JsonValue::Array(items))
+        }
+        (JsonValue::Object(mut map), JsonValue::Object(others)) => {
+            for (key, item) in others {
+                let merged = match map.remove(key) {
+                    Some(existing @ (JsonValue::Array(_) | JsonValue::Object(_))) => {
+                        merge_values(existing, item)?
+                    }
+                    Some(existing) => existing,
+                    _none => item.clone(),
+                };
+                map.insert(key.clone(), merged);
+            }
+            Ok(JsonValue::Object(map))
+        }
+        (base, other) => Err(format!(
+            "expected two lists or two maps, found {} and {}",
+            type_name(&base),
+            type_name(other)
+        )),
+    }
+}
+
+pub fn yaml_to_json(value: &serde_yaml::Value) -> Result<JsonValue, String> {
+    serde_json::to_value(value).map_err(|e| format!("unsupported value: {}", e))
+}
+
+fn type_name(value: &JsonValue) -> &'static str {
+    match value {
+        JsonValue::Null => "null",
+        JsonValue::Bool(_) => "a boolean",
+        JsonValue::Number(_) => "a number",
+        JsonValue::String(_) => "a string",
+        JsonValue::Array(_) => "a list",
+        JsonValue::Object(_) => "a map",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn concatenates_lists_without_duplicates() {
+        let merged = merge_values(
+            json!([{"Key": "Name", "Value": "vpc"}, "a"]),
+            &json!(["a", {"Key": "Env", "Value": "dev"}]),
+        )
+        .unwrap();
+        assert_eq!(
+            merged,
+            json!([{"Key": "Name", "Value": "vpc"}, "a", {"Key": "Env", "Value": "dev"}])
+        );
+    }
+
+    #[test]
+    fn merges_maps_recursively_with_base_winning() {
+        let merged = merge_values(
+            json!({"name": "vpc", "tags": {"env": "dev"}, "zones": ["a"]}),
+            &json!({"name": "other", "tags": {"team": "net"}, "zones": ["b"], "ipv6": true}),
+        )
+        .unwrap();
+        assert_eq!(
+            merged,
+            json!({
+                "name": "vpc",
+                "tags": {"env": "dev", "team": "net"},
+                "zones": ["a", "b"],
+                "ipv6": true
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_mismatched_types() {
+        assert_eq!(
+            merge_values(json!(["a"]), &json!({"a": 1})).unwrap_err(),
+            "expected two lists or two maps, found a list and a map"
+        );
+        assert_eq!(
+            merge_values(json!("a"), &json!(["a"])).unwrap_err(),
+            "expected two lists or two maps, found a string and a list"
+        );
+    }
+
+    #[test]
+    fn rejects_mismatched_nested_types() {
+        assert!(merge_values(json!({"tags": ["a"]}), &json!({"tags": {"a": 1}})).is_err());
+    }
+}
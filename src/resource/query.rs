@@ -0,0 +1,406 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// Marker that opens an anchor comment, e.g. `/*+ statecheck, retries=5 */`
+const ANCHOR_START: &str = "/*+";
+const COMMENT_END: &str = "*/";
+
+/// The lifecycle step a query in a resource file is bound to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Anchor {
+    Exists,
+    Create,
+    Update,
+    Statecheck,
+    Exports,
+    Delete,
+}
+
+impl Anchor {
+    pub const ALL: [Anchor; 6] = [
+        Anchor::Exists,
+        Anchor::Create,
+        Anchor::Update,
+        Anchor::Statecheck,
+        Anchor::Exports,
+        Anchor::Delete,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Anchor::Exists => "exists",
+            Anchor::Create => "create",
+            Anchor::Update => "update",
+            Anchor::Statecheck => "statecheck",
+            Anchor::Exports => "exports",
+            Anchor::Delete => "delete",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|anchor| anchor.name() == name)
+    }
+}
+
+impl fmt::Display for Anchor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Options declared on an anchor, e.g. `retries=5, retry_delay=5`
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryOptions {
+    /// Number of attempts made before giving up
+    pub retries: u32,
+    /// Seconds to wait between attempts
+    pub retry_delay: u64,
+    /// Number of existence checks made after a delete
+    pub postdelete_retries: u32,
+    /// Seconds to wait between existence checks after a delete
+    pub postdelete_retry_delay: u64,
+}
+
+impl Default for QueryOptions {
+    fn default() -> Self {
+        Self {
+            retries: 1,
+            retry_delay: 0,
+            postdelete_retries: 10,
+            postdelete_retry_delay: 5,
+        }
+    }
+}
+
+/// A single query bound to an anchor within a resource file
+#[derive(Debug, Clone)]
+pub struct AnchoredQuery {
+    pub anchor: Anchor,
+    pub sql: String,
+    pub options: QueryOptions,
+    /// Line of the anchor comment in the source file
+    pub line: usize,
+}
+
+/// All anchored queries parsed from a `resources/<name>.iql` file
+#[derive(Debug)]
+pub struct ResourceQueries {
+    queries: BTreeMap<Anchor, AnchoredQuery>,
+}
+
+impl ResourceQueries {
+    /// Load and parse a resource query file
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        Self::parse(&content, path)
+    }
+
+    /// Parse resource file content, `path` is only used for error reporting
+    pub fn parse(content: &str, path: &Path) -> Result<Self, String> {
+        let error =
+            |line: usize, message: String| format!("{}:{}: {}", path.display(), line, message);
+
+        let mut queries: BTreeMap<Anchor, AnchoredQuery> = BTreeMap::new();
+        let mut offset = match content.find(ANCHOR_START) {
+            Some(start) => start,
+            _none => return Err(error(1, "no anchored queries found".to_string())),
+        };
+
+        let preamble = strip_comments(&content[..offset]);
+        if !preamble.trim().is_empty() {
+            return Err(error(
+                1,
+                "query text found before the first anchor".to_string(),
+            ));
+        }
+
+        while offset < content.len() {
+            let line = line_number(content, offset);
+            let header_start = offset + ANCHOR_START.len();
+            let header_end = match content[header_start..].find(COMMENT_END) {
+                Some(end) => header_start + end,
+                _none => return Err(error(line, "unterminated anchor comment".to_string())),
+            };
+
+            let body_start = header_end + COMMENT_END.len();
+            let body_end = content[body_start..]
+                .find(ANCHOR_START)
+                .map(|next| body_start + next)
+                .unwrap_or(content.len());
+
+            let (anchor, options) = parse_anchor_header(&content[header_start..header_end])
+                .map_err(|e| error(line, e))?;
+
+            let sql = content[body_start..body_end].trim().to_string();
+            if strip_comments(&sql).trim().is_empty() {
+                return Err(error(line, format!("anchor '{}' has no query", anchor)));
+            }
+
+            if let Some(existing) = queries.get(&anchor) {
+                return Err(error(
+                    line,
+                    format!(
+                        "duplicate anchor '{}', first defined at line {}",
+                        anchor, existing.line
+                    ),
+                ));
+            }
+
+            queries.insert(
+                anchor,
+                AnchoredQuery {
+                    anchor,
+                    sql,
+                    options,
+                    line,
+                },
+            );
+            offset = body_end;
+        }
+
+        Ok(Self { queries })
+    }
+
+    /// Get the query for an anchor, if the resource defines one
+    pub fn get(&self, anchor: Anchor) -> Option<&AnchoredQuery> {
+        self.queries.get(&anchor)
+    }
+}
+
+/// Parse the text between `/*+` and `*/` into an anchor and its options
+fn parse_anchor_header(header: &str) -> Result<(Anchor, QueryOptions), String> {
+    let mut parts = header.split(',').map(str::trim);
+
+    let name = parts.next().unwrap_or("");
+    let anchor = Anchor::from_name(name).ok_or_else(|| {
+        let supported: Vec<&str> = Anchor::ALL.iter().map(|a| a.name()).collect();
+        format!(
+            "unknown anchor '{}', supported anchors are: {}",
+            name,
+            supported.join(", ")
+        )
+    })?;
+
+    let mut options = QueryOptions::default();
+    for part in parts {
+        if part.is_empty() {
+            continue;
+        }
+
+        let (key, value) = part
+            .split_once('=')
+            .map(|(k, v)| (k.trim(), v.trim()))
+            .ok_or_else(|| {
+                format!(
+                    "invalid option '{}' on anchor '{}', expected key=value",
+                    part, anchor
+                )
+            })?;
+
+        let invalid = |_| {
+            format!(
+                "invalid value '{}' for option '{}' on anchor '{}'",
+                value, key, anchor
+            )
+        };
+        match key {
+            "retries" => options.retries = value.parse().map_err(invalid)?,
+            "retry_delay" => options.retry_delay = value.parse().map_err(invalid)?,
+            "postdelete_retries" => options.postdelete_retries = value.parse().map_err(invalid)?,
+            "postdelete_retry_delay" => {
+                options.postdelete_retry_delay = value.parse().map_err(invalid)?
+            }
+            _ => {
+                return Err(format!(
+                    "unknown option '{}' on anchor '{}', supported options are: retries, retry_delay, postdelete_retries, postdelete_retry_delay",
+                    key, anchor
+                ))
+            }
+        }
+    }
+
+    Ok((anchor, options))
+}
+
+/// Remove `/* ... */` block comments and `--` line comments
+fn strip_comments(text: &str) -> String {
+    let mut result = String::new();
+    let mut rest = text;
+
+    while let Some(start) = rest.find("/*") {
+        result.push_str(&rest[..start]);
+        rest = match rest[start..].find(COMMENT_END) {
+            Some(end) => &rest[start + end + COMMENT_END.len()..],
+            _none => "",
+        };
+    }
+    result.push_str(rest);
+
+    result
+        .lines()
+        .map(|line| line.split("--").next().unwrap_or(""))
+        .collect::<Vec<&str>>()
+        .join("\n")
+}
+
+/// 1-based line number of a byte offset
+fn line_number(content: &str, offset: usize) -> usize {
+    content[..offset].matches('\n').count() + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(content: &str) -> Result<ResourceQueries, String> {
+        ResourceQueries::parse(content, Path::new("resources/vpc.iql"))
+    }
+
+    #[test]
+    fn parses_starter_resource_files() {
+        let starters = [
+            include_str!("../../template-hub/aws/starter/resources/example_vpc.iql.template"),
+            include_str!("../../template-hub/azure/starter/resources/example_res_grp.iql.template"),
+            include_str!("../../template-hub/google/starter/resources/example_vpc.iql.template"),
+        ];
+        for starter in starters {
+            let queries = parse(starter).unwrap();
+            for anchor in [
+                Anchor::Exists,
+                Anchor::Create,
+                Anchor::Statecheck,
+                Anchor::Exports,
+                Anchor::Delete,
+            ] {
+                assert!(queries.get(anchor).is_some(), "missing {}", anchor);
+            }
+        }
+    }
+
+    #[test]
+    fn parses_anchor_options_and_lines() {
+        let queries = parse(include_str!(
+            "../../template-hub/aws/starter/resources/example_vpc.iql.template"
+        ))
+        .unwrap();
+
+        let statecheck = queries.get(Anchor::Statecheck).unwrap();
+        assert_eq!(statecheck.line, 34);
+        assert_eq!(statecheck.options.retries, 5);
+        assert_eq!(statecheck.options.retry_delay, 5);
+        assert_eq!(
+            queries.get(Anchor::Delete).unwrap().options,
+            QueryOptions::default()
+        );
+        assert!(queries.get(Anchor::Update).is_none());
+    }
+
+    #[test]
+    fn keeps_query_text_between_anchors() {
+        let queries = parse(
+            "/*+ exists */\nSELECT 1;\n\n/*+ delete, postdelete_retries=3 */\nDELETE FROM t\n",
+        )
+        .unwrap();
+        assert_eq!(queries.get(Anchor::Exists).unwrap().sql, "SELECT 1;");
+        let delete = queries.get(Anchor::Delete).unwrap();
+        assert_eq!(delete.sql, "DELETE FROM t");
+        assert_eq!(delete.line, 4);
+        assert_eq!(delete.options.postdelete_retries, 3);
+    }
+
+    #[test]
+    fn allows_comments_before_the_first_anchor() {
+        assert!(parse("-- vpc\n/* notes */\n/*+ exists */\nSELECT 1").is_ok());
+    }
+
+    #[test]
+    fn rejects_a_file_without_anchors() {
+        assert_eq!(
+            parse("SELECT 1").unwrap_err(),
+            "resources/vpc.iql:1: no anchored queries found"
+        );
+    }
+
+    #[test]
+    fn rejects_query_text_before_the_first_anchor() {
+        assert_eq!(
+            parse("SELECT 1\n/*+ exists */\nSELECT 1").unwrap_err(),
+            "resources/vpc.iql:1: query text found before the first anchor"
+        );
+    }
+
+    #[test]
+    fn rejects_duplicate_anchors() {
+        assert_eq!(
+            parse("/*+ exists */\nSELECT 1\n/*+ create */\nINSERT\n/*+ exists */\nSELECT 2")
+                .unwrap_err(),
+            "resources/vpc.iql:5: duplicate anchor 'exists', first defined at line 1"
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_anchors() {
+        assert_eq!(
+            parse("/*+ exists */\nSELECT 1\n/*+ upsert */\nINSERT").unwrap_err(),
+            "resources/vpc.iql:3: unknown anchor 'upsert', supported anchors are: exists, create, update, statecheck, exports, delete"
+        );
+    }
+
+    #[test]
+    fn rejects_unterminated_anchor_comments() {
+        assert_eq!(
+            parse("/*+ exists\nSELECT 1").unwrap_err(),
+            "resources/vpc.iql:1: unterminated anchor comment"
+        );
+    }
+
+    #[test]
+    fn rejects_anchors_without_a_query() {
+        assert_eq!(
+            parse("/*+ exists */\n-- todo\n/*+ create */\nINSERT").unwrap_err(),
+            "resources/vpc.iql:1: anchor 'exists' has no query"
+        );
+    }
+
+    #[test]
+    fn rejects_bad_options() {
+        assert_eq!(
+            parse_anchor_header(" statecheck, retries ").unwrap_err(),
+            "invalid option 'retries' on anchor 'statecheck', expected key=value"
+        );
+        assert_eq!(
+            parse_anchor_header("statecheck, retries=five").unwrap_err(),
+            "invalid value 'five' for option 'retries' on anchor 'statecheck'"
+        );
+        assert_eq!(
+            parse_anchor_header("statecheck, retry_delay=-1").unwrap_err(),
+            "invalid value '-1' for option 'retry_delay' on anchor 'statecheck'"
+        );
+        assert!(parse_anchor_header("statecheck, timeout=5")
+            .unwrap_err()
+            .starts_with("unknown option 'timeout' on anchor 'statecheck'"));
+    }
+
+    #[test]
+    fn reports_the_line_of_a_bad_option() {
+        assert_eq!(
+            parse("/*+ exists */\nSELECT 1\n\n/*+ statecheck, retries=x */\nSELECT 1").unwrap_err(),
+            "resources/vpc.iql:4: invalid value 'x' for option 'retries' on anchor 'statecheck'"
+        );
+    }
+
+    #[test]
+    fn parses_anchor_headers() {
+        let (anchor, options) =
+            parse_anchor_header(" delete, retries=20, retry_delay=10, postdelete_retry_delay=2,")
+                .unwrap();
+        assert_eq!(anchor, Anchor::Delete);
+        assert_eq!(options.retries, 20);
+        assert_eq!(options.retry_delay, 10);
+        assert_eq!(options.postdelete_retries, 10);
+        assert_eq!(options.postdelete_retry_delay, 2);
+    }
+}
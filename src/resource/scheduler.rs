@@ -0,0 +1,178 @@
+use crate::resource::graph::DependencyGraph;
+use crate::utils::display::set_log_prefix;
+use std::collections::HashSet;
+use std::sync::{Condvar, Mutex};
+use std::thread;
+
+/// Direction to walk the dependency graph in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Dependencies before the resources that use them, as in build
+    Forward,
+    /// Resources before their dependencies, as in teardown
+    Reverse,
+}
+
+struct State {
+    pending: Vec<String>,
+    done: HashSet<String>,
+    failures: Vec<(String, String)>,
+}
+
+/// Records a task that panics as a failure and wakes the waiting workers, which would
+/// otherwise wait forever for it to complete
+struct PanicGuard<'a> {
+    state: &'a Mutex<State>,
+    ready: &'a Condvar,
+    name: &'a str,
+}
+
+impl Drop for PanicGuard<'_> {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+            state
+                .failures
+                .push((self.name.to_string(), "task panicked".to_string()));
+            self.ready.notify_all();
+        }
+    }
+}
+
+/// Run `task` once per resource in `names`, starting a resource only when the resources it
+/// must wait for have completed and running up to `parallelism` resources at a time.
+/// No new resources are started after a failure, the failures are returned once running
+/// tasks have finished. A panicking task stops scheduling the same way, then the panic is
+/// propagated.
+pub fn run_scheduled<F>(
+    graph: &DependencyGraph,
+    names: &[String],
+    direction: Direction,
+    parallelism: usize,
+    task: F,
+) -> Result<(), Vec<(String, String)>>
+where
+    F: Fn(&str) -> Result<(), String> + Sync,
+{
+    let prerequisites = |name: &str| -> Vec<String> {
+        match direction {
+            Direction::Forward => graph
+                .dependencies(name)
+                .map(|(dependency, _)| dependency.clone())
+                .filter(|dependency| names.contains(dependency))
+                .collect(),
+            Direction::Reverse => names
+                .iter()
+                .filter(|other| graph.dependencies(other).any(|(d, _)| d == name))
+                .cloned()
+                .collect(),
+        }
+    };
+
+    // Among resources that are ready, prefer the order of `names`, reversed for teardown
+    let mut pending = names.to_vec();
+    if direction == Direction::Reverse {
+        pending.reverse();
+    }
+
+    let state = Mutex::new(State {
+        pending,
+        done: HashSet::new(),
+        failures: Vec::new(),
+    });
+    let ready = Condvar::new();
+    let workers = parallelism.clamp(1, names.len().max(1));
+
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| loop {
+                let name = {
+                    let mut state = state.lock().unwrap();
+                    loop {
+                        if !state.failures.is_empty() || state.pending.is_empty() {
+                            return;
+                        }
+                        let next = state.pending.iter().position(|name| {
+                            prerequisites(name)
+                                .iter()
+                                .all(|prerequisite| state.done.contains(prerequisite))
+                        });
+                        if let Some(index) = next {
+                            break state.pending.remove(index);
+                        }
+                        state = ready.wait(state).unwrap();
+                    }
+                };
+
+                if workers > 1 {
+                    set_log_prefix(Some(&name));
+                }
+                let guard = PanicGuard {
+                    state: &state,
+                    ready: &ready,
+                    name: &name,
+                };
+                let result = task(&name);
+                drop(guard);
+                set_log_prefix(None);
+
+                let mut state = state.lock().unwrap();
+                match result {
+                    Ok(()) => {
+                        state.done.insert(name);
+                    }
+                    Err(e) => state.failures.push((name, e)),
+                }
+                ready.notify_all();
+            });
+        }
+    });
+
+    let state = state.into_inner().unwrap();
+    if state.failures.is_empty() {
+        Ok(())
+    } else {
+        Err(state.failures)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resource::manifest::Manifest;
+    use crate::test_support::temp_stack;
+    use std::panic::{self, AssertUnwindSafe};
+
+    #[test]
+    fn a_panicking_task_does_not_leave_workers_waiting() {
+        let stack_dir = temp_stack(
+            "version: 1\nname: net\nresources:\n  - name: vpc\n    exports: [vpc_id]\n  - name: subnet\n",
+            &[
+                ("vpc", "/*+ create */\nINSERT INTO vpcs SELECT 1"),
+                ("subnet", "/*+ create */\nINSERT INTO subnets SELECT '{{ vpc_id }}'"),
+            ],
+        );
+        let manifest = Manifest::load(stack_dir.path()).unwrap();
+        let ran = Mutex::new(Vec::new());
+
+        // the second worker waits for vpc, which panics
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            run_scheduled(
+                &manifest.graph,
+                manifest.graph.order(),
+                Direction::Forward,
+                2,
+                |name| {
+                    ran.lock().unwrap().push(name.to_string());
+                    if name == "vpc" {
+                        panic!("provider crashed");
+                    }
+                    Ok(())
+                },
+            )
+        }));
+
+        assert!(result.is_err());
+        assert_eq!(*ran.lock().unwrap(), ["vpc"]);
+    }
+}
@@ -0,0 +1,136 @@
+use crate::resource::manifest::{Manifest, Resource};
+use crate::resource::props::{resolve_prop, yaml_to_json};
+use crate::template::engine::render_value;
+use serde_json::Value as JsonValue;
+use std::collections::BTreeMap;
+use tera::Context;
+
+/// Sources of template variables, later layers take precedence over earlier ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Layer {
+    /// `stack_name` and `stack_env`
+    Builtin,
+    /// Process environment, `--env-file` and `-e` variables
+    Env,
+    /// Exports imported from other stacks
+    Import,
+    /// Manifest `globals`
+    Global,
+    /// Props of the resource being rendered
+    Prop,
+    /// Exports captured from resources processed earlier
+    Export,
+}
+
+/// Layered variables used to render a stack's queries
+#[derive(Debug, Clone, Default)]
+pub struct StackContext {
+    layers: BTreeMap<Layer, BTreeMap<String, JsonValue>>,
+}
+
+impl StackContext {
+    /// Context holding the built-in variables, environment variables, imported exports
+    /// and the manifest globals
+    pub fn for_stack(
+        manifest: &Manifest,
+        stack_env: &str,
+        env_vars: &BTreeMap<String, String>,
+        imports: &BTreeMap<String, JsonValue>,
+    ) -> Result<Self, String> {
+        let mut context = Self::base(manifest, stack_env, env_vars);
+
+        for (name, value) in imports {
+            context.set(Layer::Import, name, value.clone());
+        }
+
+        for global in &manifest.globals {
+            let value = render_value(
+                &yaml_to_json(&global.value)?,
+                &context,
+                &format!("global '{}'", global.name),
+            )?;
+            context.set(Layer::Global, &global.name, value);
+        }
+
+        Ok(context)
+    }
+
+    /// Context holding only the built-in variables and environment variables
+    pub fn base(manifest: &Manifest, stack_env: &str, env_vars: &BTreeMap<String, String>) -> Self {
+        let mut context = Self::default();
+        context.set(
+            Layer::Builtin,
+            "stack_name",
+            JsonValue::String(manifest.name.clone()),
+        );
+        context.set(
+            Layer::Builtin,
+            "stack_env",
+            JsonValue::String(stack_env.to_string()),
+        );
+
+        for (name, value) in env_vars {
+            context.set(Layer::Env, name, JsonValue::String(value.clone()));
+        }
+
+        context
+    }
+
+    /// Copy of the context extended with a resource's props for the target environment
+    pub fn with_resource(&self, resource: &Resource, stack_env: &str) -> Result<Self, String> {
+        let mut context = self.clone();
+        for prop in &resource.props {
+            let location = format!("resource '{}' prop '{}'", resource.name, prop.name);
+            let value = resolve_prop(prop, stack_env, &context, &location)?;
+            context.set(Layer::Prop, &prop.name, value);
+        }
+        Ok(context)
+    }
+
+    /// Set a variable in a layer, replacing any previous value in that layer
+    pub fn set(&mut self, layer: Layer, name: &str, value: JsonValue) {
+        self.layers
+            .entry(layer)
+            .or_default()
+            .insert(name.to_string(), value);
+    }
+
+    /// Look up a variable, later layers take precedence
+    pub fn get(&self, name: &str) -> Option<&JsonValue> {
+        self.layers.values().rev().find_map(|vars| vars.get(name))
+    }
+
+    /// Variables set in a single layer
+    pub fn layer(&self, layer: Layer) -> impl Iterator<Item = (&String, &JsonValue)> {
+        self.layers.get(&layer).into_iter().flatten()
+    }
+
+    /// Record an export captured from a resource
+    pub fn insert_export(&mut self, name: &str, value: JsonValue) {
+        self.set(Layer::Export, name, value);
+    }
+
+    /// Record the exports captured from a resource
+    pub fn insert_exports(&mut self, exports: &BTreeMap<String, JsonValue>) {
+        for (name, value) in exports {
+            self.insert_export(name, value.clone());
+        }
+    }
+
+    /// Flatten the layers into a Tera context
+    pub fn to_tera(&self) -> Context {
+        let mut context = Context::new();
+        for vars in self.layers.values() {
+            for (name, value) in vars {
+                match value {
+                    // lists and maps are passed to queries as JSON text
+                    JsonValue::Array(_) | JsonValue::Object(_) => {
+                        context.insert(name.as_str(), &value.to_string())
+                    }
+                    _ => context.insert(name.as_str(), value),
+                }
+            }
+        }
+        context
+    }
+}
@@ -0,0 +1,137 @@
+use crate::template::context::StackContext;
+use serde_json::Value as JsonValue;
+use std::collections::BTreeSet;
+use std::error::Error;
+use tera::{Context, Tera};
+
+/// Render a template string against a stack context,
+/// `location` names where the template came from, e.g. "resource 'vpc' anchor 'create'"
+pub fn render(template: &str, context: &StackContext, location: &str) -> Result<String, String> {
+    render_template(template, &context.to_tera()).map_err(|e| format!("{} in {}", e, location))
+}
+
+/// Render a template string against a plain Tera context
+pub fn render_template(template: &str, context: &Context) -> Result<String, String> {
+    let mut tera = Tera::default();
+    tera.add_raw_template("template", template)
+        .map_err(|e| format!("invalid template: {}", describe_error(&e)))?;
+    tera.render("template", context)
+        .map_err(|e| describe_error(&e))
+}
+
+/// Render every string within a (possibly nested) value
+pub fn render_value(
+    value: &JsonValue,
+    context: &StackContext,
+    location: &str,
+) -> Result<JsonValue, String> {
+    match value {
+        JsonValue::String(s) => Ok(JsonValue::String(render(s, context, location)?)),
+        JsonValue::Array(items) => Ok(JsonValue::Array(
+            items
+                .iter()
+                .map(|item| render_value(item, context, location))
+                .collect::<Result<_, _>>()?,
+        )),
+        JsonValue::Object(map) => {
+            let mut rendered = serde_json::Map::new();
+            for (key, item) in map {
+                rendered.insert(key.clone(), render_value(item, context, location)?);
+            }
+            Ok(JsonValue::Object(rendered))
+        }
+        _ => Ok(value.clone()),
+    }
+}
+
+/// Summarise a Tera error, naming the variable when one is undefined
+fn describe_error(error: &tera::Error) -> String {
+    let mut messages = vec![error.to_string()];
+    let mut source = error.source();
+    while let Some(e) = source {
+        messages.push(e.to_string());
+        source = e.source();
+    }
+
+    // Tera reports undefined variables as "Variable `name` not found in context ..."
+    for message in &messages {
+        if let Some(rest) = message.strip_prefix("Variable `") {
+            if let Some((name, _)) = rest.split_once('`') {
+                return format!("undefined variable '{}'", name);
+            }
+        }
+    }
+
+    messages
+        .into_iter()
+        .filter(|message| !message.starts_with("Failed to render"))
+        .collect::<Vec<_>>()
+        .join(": ")
+}
+
+/// Tera keywords and literals that are never variable names
+const KEYWORDS: [&str; 20] = [
+    "if", "elif", "else", "endif", "for", "endfor", "in", "and", "or", "not", "is", "set",
+    "endset", "raw", "endraw", "true", "false", "True", "False", "loop",
+];
+
+/// Names of the top level variables referenced by a template's `{{ }}` and `{% %}` tags
+pub fn referenced_variables(template: &str) -> BTreeSet<String> {
+    let mut names = BTreeSet::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find(['{']) {
+        let close = match &rest[start..] {
+            tag if tag.starts_with("{{") => "}}",
+            tag if tag.starts_with("{%") => "%}",
+            _ => {
+                rest = &rest[start + 1..];
+                continue;
+            }
+        };
+
+        let body_start = start + 2;
+        let body_end = match rest[body_start..].find(close) {
+            Some(end) => body_start + end,
+            _none => break,
+        };
+        collect_identifiers(&rest[body_start..body_end], &mut names);
+        rest = &rest[body_end + close.len()..];
+    }
+
+    names
+}
+
+/// Collect identifiers from a tag body, skipping string literals, filters and attributes
+fn collect_identifiers(expression: &str, names: &mut BTreeSet<String>) {
+    let chars: Vec<char> = expression.chars().collect();
+    let mut previous = ' ';
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '"' || c == '\'' || c == '`' {
+            i += 1;
+            while i < chars.len() && chars[i] != c {
+                i += 1;
+            }
+            previous = c;
+            i += 1;
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            let name: String = chars[start..i].iter().collect();
+            if previous != '|' && previous != '.' && !KEYWORDS.contains(&name.as_str()) {
+                names.insert(name);
+            }
+            previous = 'a';
+        } else {
+            if !c.is_whitespace() {
+                previous = c;
+            }
+            i += 1;
+        }
+    }
+}
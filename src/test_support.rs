@@ -0,0 +1,31 @@
+//! Helpers shared by unit tests
+
+use crate::resource::manifest::MANIFEST_FILE_NAME;
+use std::fs;
+use tempfile::{NamedTempFile, TempDir};
+
+/// Write a stack to a temp directory removed on drop, `resources` pairs names with
+/// query files
+pub fn temp_stack(manifest: &str, resources: &[(&str, &str)]) -> TempDir {
+    let temp_dir = TempDir::new().unwrap();
+    let stack_dir = temp_dir.path();
+    fs::create_dir_all(stack_dir.join("resources")).unwrap();
+    fs::write(stack_dir.join(MANIFEST_FILE_NAME), manifest).unwrap();
+    for (resource, queries) in resources {
+        fs::write(
+            stack_dir
+                .join("resources")
+                .join(format!("{}.iql", resource)),
+            queries,
+        )
+        .unwrap();
+    }
+    temp_dir
+}
+
+/// Write `content` to a temp file removed on drop
+pub fn temp_file(content: &str) -> NamedTempFile {
+    let file = NamedTempFile::new().unwrap();
+    fs::write(file.path(), content).unwrap();
+    file
+}
@@ -1,21 +1,65 @@
 use std::env;
 use std::path::PathBuf;
 use std::process::Command;
+use std::sync::OnceLock;
+
+/// Binary selected for this run, e.g. a pinned release, used instead of looking one up
+static SELECTED_BINARY: OnceLock<PathBuf> = OnceLock::new();
 
 /// Check if the stackql binary exists in PATH
 pub fn binary_exists_in_path() -> bool {
     let binary_name = super::platform::get_binary_name();
-    let status = if super::platform::get_platform() == super::platform::Platform::Windows {
-        Command::new("where").arg(&binary_name).status()
+    // Capture output so the lookup doesn't print the path to our stdout
+    let output = if super::platform::get_platform() == super::platform::Platform::Windows {
+        Command::new("where").arg(&binary_name).output()
     } else {
-        Command::new("which").arg(&binary_name).status()
+        Command::new("which").arg(&binary_name).output()
     };
 
-    status.map(|s| s.success()).unwrap_or(false)
+    output.map(|o| o.status.success()).unwrap_or(false)
+}
+
+/// Per-user directory stackql-deploy keeps downloads and server state in
+pub fn cache_dir() -> PathBuf {
+    dirs::cache_dir()
+        .unwrap_or_else(env::temp_dir)
+        .join("stackql-deploy")
+}
+
+/// Per-user directory the stackql binary is downloaded into when it is not installed
+pub fn binary_cache_dir() -> PathBuf {
+    cache_dir().join("bin")
+}
+
+/// Directory `upgrade` installs the latest binary into. A binary in the current
+/// directory is found before the cache, so it is replaced in place, otherwise the
+/// cache is used, which is searched before the PATH
+pub fn upgrade_install_dir() -> PathBuf {
+    let binary_name = super::platform::get_binary_name();
+    match env::current_dir() {
+        Ok(current_dir) if current_dir.join(&binary_name).is_file() => current_dir,
+        _ => binary_cache_dir(),
+    }
+}
+
+/// Path a pinned release is cached at, e.g. `<cache>/v0.5.612/stackql`
+pub fn pinned_binary_path(version: &str) -> PathBuf {
+    binary_cache_dir()
+        .join(version)
+        .join(super::platform::get_binary_name())
+}
+
+/// Use a specific binary for the rest of this run
+pub fn select_binary(path: PathBuf) {
+    SELECTED_BINARY.set(path).ok();
 }
 
 /// Get the full path to the stackql binary
 pub fn get_binary_path() -> Option<PathBuf> {
+    if let Some(path) = SELECTED_BINARY.get() {
+        return Some(path.clone());
+    }
+
     let binary_name = super::platform::get_binary_name();
 
     // First check current directory
@@ -26,6 +70,12 @@ pub fn get_binary_path() -> Option<PathBuf> {
         }
     }
 
+    // Then the download cache
+    let binary_path = binary_cache_dir().join(&binary_name);
+    if binary_path.is_file() {
+        return Some(binary_path);
+    }
+
     // Then check PATH
     if binary_exists_in_path() {
         if let Ok(paths) = env::var("PATH") {
@@ -1,6 +1,47 @@
 use colored::*;
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, Ordering};
 use unicode_width::UnicodeWidthStr;
 
+/// Whether stdout is reserved for a JSON document, see `--output json`
+static JSON_OUTPUT: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    /// Prefix for messages printed from the current thread, e.g. the resource a worker is on
+    static LOG_PREFIX: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Set or clear the prefix for messages printed from the current thread
+pub fn set_log_prefix(prefix: Option<&str>) {
+    LOG_PREFIX.with(|p| *p.borrow_mut() = prefix.map(|s| s.to_string()));
+}
+
+/// Reserve stdout for a JSON document, human readable output then goes to stderr
+pub fn set_json_output(enabled: bool) {
+    JSON_OUTPUT.store(enabled, Ordering::SeqCst);
+}
+
+/// Whether the command should print its results as a JSON document
+pub fn json_output() -> bool {
+    JSON_OUTPUT.load(Ordering::SeqCst)
+}
+
+/// Print a line of human readable output, on stderr when stdout carries JSON
+pub fn print_line(message: &str) {
+    if json_output() {
+        eprintln!("{}", message);
+    } else {
+        println!("{}", message);
+    }
+}
+
+fn prefixed(message: &str) -> String {
+    LOG_PREFIX.with(|p| match p.borrow().as_deref() {
+        Some(prefix) => format!("{} | {}", prefix, message),
+        _none => message.to_string(),
+    })
+}
+
 /// Utility function to print a Unicode-styled message box
 /// that correctly handles the width of emojis and other wide characters
 pub fn print_unicode_box(message: &str) {
@@ -28,28 +69,27 @@ pub fn print_unicode_box(message: &str) {
         reset_color
     );
 
-    println!("{}", top_border);
+    print_line(&top_border);
     for line in lines {
         // Calculate proper padding based on the visual width
         let padding = max_length - UnicodeWidthStr::width(line);
         let padded_line = format!("│ {}{} │", line, " ".repeat(padding));
-        println!("{}{}{}", border_color, padded_line, reset_color);
+        print_line(&format!("{}{}{}", border_color, padded_line, reset_color));
     }
-    println!("{}", bottom_border);
+    print_line(&bottom_border);
 }
 
 /// Print an error message in red
 pub fn print_error(message: &str) {
-    eprintln!("{}", message.red());
+    eprintln!("{}", prefixed(message).red());
 }
 
 /// Print a success message in green
-#[allow(dead_code)]
 pub fn print_success(message: &str) {
-    println!("{}", message.green());
+    print_line(&prefixed(message).green().to_string());
 }
 
 /// Print an info message in blue
 pub fn print_info(message: &str) {
-    println!("{}", message.blue());
+    print_line(&prefixed(message).blue().to_string());
 }
@@ -1,82 +1,346 @@
 use crate::error::AppError;
+use crate::utils::binary::{binary_cache_dir, pinned_binary_path, upgrade_install_dir};
 use crate::utils::display::print_info;
-use crate::utils::platform::{get_platform, Platform};
+use crate::utils::platform::{get_platform, get_target, Arch, Libc, Platform, Target};
 use indicatif::{ProgressBar, ProgressStyle};
 use reqwest::blocking::Client;
+use reqwest::header::RANGE;
+use reqwest::StatusCode;
+use sha2::{Digest, Sha256};
 use std::fs::{self, File};
-use std::io::{self, Write};
+use std::io::{self, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::thread;
+use std::time::Duration;
 use zip::ZipArchive;
 
-pub fn get_download_url() -> Result<String, AppError> {
-    match get_platform() {
-        Platform::Linux => Ok("https://releases.stackql.io/stackql/latest/stackql_linux_amd64.zip".to_string()),
-        Platform::Windows => Ok("https://releases.stackql.io/stackql/latest/stackql_windows_amd64.zip".to_string()),
-        Platform::MacOS => Ok("https://storage.googleapis.com/stackql-public-releases/latest/stackql_darwin_multiarch.pkg".to_string()),
-        Platform::Unknown => Err(AppError::CommandFailed("Unsupported OS".to_string())),
+const RELEASES_URL: &str = "https://releases.stackql.io/stackql";
+const MACOS_RELEASES_URL: &str = "https://storage.googleapis.com/stackql-public-releases";
+const DOWNLOAD_ATTEMPTS: u32 = 5;
+
+/// Release artifacts published for each supported target, with the base URL they are under
+const RELEASE_ARTIFACTS: [(Target, &str, &str); 5] = [
+    (
+        Target {
+            platform: Platform::Linux,
+            arch: Arch::Amd64,
+            libc: Some(Libc::Glibc),
+        },
+        RELEASES_URL,
+        "stackql_linux_amd64.zip",
+    ),
+    (
+        Target {
+            platform: Platform::Linux,
+            arch: Arch::Arm64,
+            libc: Some(Libc::Glibc),
+        },
+        RELEASES_URL,
+        "stackql_linux_arm64.zip",
+    ),
+    (
+        Target {
+            platform: Platform::Windows,
+            arch: Arch::Amd64,
+            libc: None,
+        },
+        RELEASES_URL,
+        "stackql_windows_amd64.zip",
+    ),
+    (
+        Target {
+            platform: Platform::MacOS,
+            arch: Arch::Amd64,
+            libc: None,
+        },
+        MACOS_RELEASES_URL,
+        "stackql_darwin_multiarch.pkg",
+    ),
+    (
+        Target {
+            platform: Platform::MacOS,
+            arch: Arch::Arm64,
+            libc: None,
+        },
+        MACOS_RELEASES_URL,
+        "stackql_darwin_multiarch.pkg",
+    ),
+];
+
+/// Release URL for this host, for the latest or a pinned version, e.g. `v0.5.612`
+pub fn get_download_url(version: Option<&str>) -> Result<String, AppError> {
+    let release = version.unwrap_or("latest");
+    let target = get_target();
+    match RELEASE_ARTIFACTS.iter().find(|(t, _, _)| *t == target) {
+        Some((_, base_url, artifact)) => Ok(format!("{}/{}/{}", base_url, release, artifact)),
+        _none => {
+            let supported: Vec<String> = RELEASE_ARTIFACTS
+                .iter()
+                .map(|(t, _, _)| t.to_string())
+                .collect();
+            Err(AppError::CommandFailed(format!(
+                "No stackql release is published for {}, supported targets are: {}",
+                target,
+                supported.join(", ")
+            )))
+        }
     }
 }
 
+/// Download the latest stackql binary into the per-user cache directory
 pub fn download_binary() -> Result<PathBuf, AppError> {
-    let download_url = get_download_url()?;
-    let current_dir = std::env::current_dir().map_err(AppError::IoError)?;
+    install_binary(None, &binary_cache_dir())
+}
+
+/// Download the latest stackql binary over the one `get_binary_path` finds, so the
+/// upgraded binary is the one used afterwards
+pub fn upgrade_binary() -> Result<PathBuf, AppError> {
+    install_binary(None, &upgrade_install_dir())
+}
+
+/// Download a pinned release into its own cache directory, verifying the archive against
+/// the SHA-256 checksum published alongside it
+pub fn download_version(version: &str) -> Result<PathBuf, AppError> {
+    let install_dir = pinned_binary_path(version)
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_else(binary_cache_dir);
+    install_binary(Some(version), &install_dir)
+}
+
+fn install_binary(version: Option<&str>, install_dir: &Path) -> Result<PathBuf, AppError> {
+    let download_url = get_download_url(version)?;
+    fs::create_dir_all(install_dir).map_err(AppError::IoError)?;
     let binary_name = crate::utils::platform::get_binary_name();
     let archive_name = Path::new(&download_url)
         .file_name()
         .ok_or_else(|| AppError::CommandFailed("Invalid URL".to_string()))?
         .to_string_lossy()
         .to_string();
-    let archive_path = current_dir.join(&archive_name);
+    let archive_path = install_dir.join(format!("{}.part", archive_name));
+    let staging_dir = install_dir.join(format!(".staging-{}", std::process::id()));
 
-    // Download the file with progress bar
     print_info(&format!("Downloading from {}", download_url));
     let client = Client::new();
-    let response = client
-        .get(&download_url)
-        .send()
-        .map_err(|e| AppError::CommandFailed(format!("Failed to download: {}", e)))?;
+    let result = download_to_file(&client, &download_url, &archive_path)
+        .and_then(|_| match version {
+            Some(_) => verify_checksum(&client, &download_url, &archive_path),
+            _none => Ok(()),
+        })
+        .and_then(|_| {
+            // Extract next to the final location so the swap below is a same-filesystem rename
+            print_info("Extracting the binary...");
+            if staging_dir.exists() {
+                fs::remove_dir_all(&staging_dir).map_err(AppError::IoError)?;
+            }
+            fs::create_dir_all(&staging_dir).map_err(AppError::IoError)?;
+            extract_binary(&archive_path, &staging_dir, &binary_name)
+        })
+        .and_then(|staged_binary| {
+            // Set executable permissions on Unix-like systems
+            if get_platform() != Platform::Windows {
+                Command::new("chmod")
+                    .arg("+x")
+                    .arg(&staged_binary)
+                    .output()
+                    .map_err(|e| {
+                        AppError::CommandFailed(format!(
+                            "Failed to set executable permission: {}",
+                            e
+                        ))
+                    })?;
+            }
+
+            // Atomically replace any existing binary, it is never left half-written
+            let binary_path = install_dir.join(&binary_name);
+            fs::rename(&staged_binary, &binary_path).map_err(AppError::IoError)?;
+            Ok(binary_path)
+        });
 
-    let total_size = response.content_length().unwrap_or(0);
-    let progress_bar = ProgressBar::new(total_size);
+    // Clean up the archive and staging directory whether or not the install succeeded
+    fs::remove_file(&archive_path).ok();
+    fs::remove_dir_all(&staging_dir).ok();
+
+    let binary_path = result?;
+    print_info(&format!(
+        "StackQL executable successfully installed at: {}",
+        binary_path.display()
+    ));
+    Ok(binary_path)
+}
+
+/// Stream `url` into `path` with a live progress bar, resuming with a `Range`
+/// request when the connection drops part way through. Only transient failures are
+/// retried, a 4xx response fails straight away
+fn download_to_file(client: &Client, url: &str, path: &Path) -> Result<(), AppError> {
+    let progress_bar = ProgressBar::new(0);
     progress_bar.set_style(
         ProgressStyle::default_bar()
             .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})")
             .unwrap()
             .progress_chars("#>-"));
 
-    let mut file = File::create(&archive_path).map_err(AppError::IoError)?;
-    let mut _downloaded: u64 = 0;
-    let stream = response
-        .bytes()
-        .map_err(|e| AppError::CommandFailed(format!("Failed to read response: {}", e)))?;
+    let mut file = File::create(path).map_err(AppError::IoError)?;
+    let mut downloaded: u64 = 0;
+    let mut attempt = 1;
 
-    file.write_all(&stream).map_err(AppError::IoError)?;
-    progress_bar.finish_with_message("Download complete");
+    loop {
+        match download_chunks(client, url, &mut file, &mut downloaded, &progress_bar) {
+            Ok(()) => {
+                progress_bar.finish_with_message("Download complete");
+                return file.sync_all().map_err(AppError::IoError);
+            }
+            Err(ChunkError::Transient(e)) if attempt < DOWNLOAD_ATTEMPTS => {
+                progress_bar.suspend(|| {
+                    print_info(&format!(
+                        "Download interrupted at {} bytes ({}), retrying ({}/{})...",
+                        downloaded,
+                        e,
+                        attempt + 1,
+                        DOWNLOAD_ATTEMPTS
+                    ))
+                });
+                thread::sleep(Duration::from_secs(1 << attempt));
+                attempt += 1;
+            }
+            Err(ChunkError::Transient(e)) => {
+                progress_bar.abandon();
+                return Err(AppError::CommandFailed(format!(
+                    "Failed to download {} after {} attempts: {}",
+                    url, DOWNLOAD_ATTEMPTS, e
+                )));
+            }
+            Err(ChunkError::Fatal(e)) => {
+                progress_bar.abandon();
+                return Err(AppError::CommandFailed(format!(
+                    "Failed to download {}: {}",
+                    url, e
+                )));
+            }
+        }
+    }
+}
 
-    // Extract the file based on platform
-    print_info("Extracting the binary...");
-    let binary_path = extract_binary(&archive_path, &current_dir, &binary_name)?;
+/// Why a download request failed, only transient failures are retried
+#[derive(Debug)]
+enum ChunkError {
+    /// Connection errors, 5xx responses and bodies cut short
+    Transient(String),
+    /// Anything a retry would not fix, e.g. a 404 or a failed write
+    Fatal(String),
+}
 
-    // Clean up the archive
-    fs::remove_file(&archive_path).ok();
+impl From<reqwest::Error> for ChunkError {
+    fn from(error: reqwest::Error) -> Self {
+        let transient = match error.status() {
+            Some(status) => status.is_server_error(),
+            _none => error.is_connect() || error.is_timeout() || error.is_body(),
+        };
+        if transient {
+            ChunkError::Transient(error.to_string())
+        } else {
+            ChunkError::Fatal(error.to_string())
+        }
+    }
+}
 
-    // Set executable permissions on Unix-like systems
-    if get_platform() != Platform::Windows {
-        Command::new("chmod")
-            .arg("+x")
-            .arg(&binary_path)
-            .output()
-            .map_err(|e| {
-                AppError::CommandFailed(format!("Failed to set executable permission: {}", e))
-            })?;
+/// Run one request, continuing from `downloaded` bytes when the server honours `Range`
+fn download_chunks(
+    client: &Client,
+    url: &str,
+    file: &mut File,
+    downloaded: &mut u64,
+    progress_bar: &ProgressBar,
+) -> Result<(), ChunkError> {
+    let fatal = |e: io::Error| ChunkError::Fatal(e.to_string());
+
+    let mut request = client.get(url);
+    if *downloaded > 0 {
+        request = request.header(RANGE, format!("bytes={}-", downloaded));
     }
+    let mut response = request.send()?.error_for_status()?;
 
-    print_info(&format!(
-        "StackQL executable successfully installed at: {}",
-        binary_path.display()
-    ));
-    Ok(binary_path)
+    if response.status() != StatusCode::PARTIAL_CONTENT && *downloaded > 0 {
+        // the server ignored the range, start over from the beginning
+        *downloaded = 0;
+        file.set_len(0).map_err(fatal)?;
+        file.seek(SeekFrom::Start(0)).map_err(fatal)?;
+        progress_bar.set_position(0);
+    }
+
+    let total_size = response.content_length().map(|len| len + *downloaded);
+    if let Some(total_size) = total_size {
+        progress_bar.set_length(total_size);
+    }
+
+    let mut buffer = [0u8; 64 * 1024];
+    loop {
+        // a connection reset while reading is as transient as a short read
+        let read = response
+            .read(&mut buffer)
+            .map_err(|e| ChunkError::Transient(e.to_string()))?;
+        if read == 0 {
+            break;
+        }
+        file.write_all(&buffer[..read]).map_err(fatal)?;
+        *downloaded += read as u64;
+        progress_bar.set_position(*downloaded);
+    }
+
+    match total_size {
+        Some(total_size) if *downloaded < total_size => Err(ChunkError::Transient(format!(
+            "connection closed after {} of {} bytes",
+            downloaded, total_size
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Compare the downloaded archive with the checksum published at `<url>.sha256`
+fn verify_checksum(
+    client: &Client,
+    download_url: &str,
+    archive_path: &Path,
+) -> Result<(), AppError> {
+    let checksum_url = format!("{}.sha256", download_url);
+    let response = client
+        .get(&checksum_url)
+        .send()
+        .map_err(|e| AppError::CommandFailed(format!("Failed to download checksum: {}", e)))?;
+    if response.status() == StatusCode::NOT_FOUND {
+        return Err(AppError::CommandFailed(format!(
+            "No SHA-256 checksum is published at {}, the download cannot be verified",
+            checksum_url
+        )));
+    }
+    let response = response
+        .error_for_status()
+        .map_err(|e| AppError::CommandFailed(format!("Failed to download checksum: {}", e)))?;
+    let published = response
+        .text()
+        .map_err(|e| AppError::CommandFailed(format!("Failed to read checksum: {}", e)))?;
+
+    // checksum files hold the hex digest, optionally followed by the file name
+    let expected = published
+        .split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .to_lowercase();
+    let mut hasher = Sha256::new();
+    let mut archive = File::open(archive_path).map_err(AppError::IoError)?;
+    io::copy(&mut archive, &mut hasher).map_err(AppError::IoError)?;
+    let actual = hex::encode(hasher.finalize());
+
+    if expected != actual {
+        return Err(AppError::CommandFailed(format!(
+            "Checksum mismatch for {}: expected {}, got {}",
+            download_url, expected, actual
+        )));
+    }
+
+    print_info(&format!("Verified SHA-256 checksum {}", actual));
+    Ok(())
 }
 
 fn extract_binary(
@@ -162,3 +426,276 @@ fn extract_binary(
 
     Ok(binary_path)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_file;
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use tempfile::NamedTempFile;
+
+    const BODY: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+
+    /// How the stand-in server answers
+    #[derive(Clone, Copy)]
+    struct Behaviour {
+        status: u16,
+        honour_range: bool,
+        /// Bytes sent before closing the first connection, `None` sends everything
+        truncate_first: Option<usize>,
+    }
+
+    /// Serve `content` on a local port, returning its URL and the `Range` header of each request
+    fn serve(behaviour: Behaviour, content: Vec<u8>) -> (String, Arc<Mutex<Vec<Option<String>>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/stackql.zip", listener.local_addr().unwrap());
+        let ranges = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&ranges);
+
+        thread::spawn(move || {
+            for (index, stream) in listener.incoming().enumerate() {
+                let mut stream = stream.unwrap();
+                let mut range = None;
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    let line = line.trim_end();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = line.split_once(':') {
+                        if name.eq_ignore_ascii_case("range") {
+                            range = Some(value.trim().to_string());
+                        }
+                    }
+                }
+                seen.lock().unwrap().push(range.clone());
+
+                let start = match range {
+                    Some(range) if behaviour.honour_range => range
+                        .trim_start_matches("bytes=")
+                        .trim_end_matches('-')
+                        .parse::<usize>()
+                        .unwrap(),
+                    _ => 0,
+                };
+                let (status, body) = match behaviour.status {
+                    200 if start > 0 => ("206 Partial Content", &content[start..]),
+                    200 => ("200 OK", &content[..]),
+                    404 => ("404 Not Found", &b""[..]),
+                    _ => ("503 Service Unavailable", &b""[..]),
+                };
+                let sent = match behaviour.truncate_first {
+                    Some(limit) if index == 0 => &body[..limit],
+                    _ => body,
+                };
+                let head = format!(
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    status,
+                    body.len()
+                );
+                stream.write_all(head.as_bytes()).ok();
+                stream.write_all(sent).ok();
+            }
+        });
+
+        (url, ranges)
+    }
+
+    #[test]
+    fn resumes_a_truncated_download_with_a_range_request() {
+        let (url, ranges) = serve(
+            Behaviour {
+                status: 200,
+                honour_range: true,
+                truncate_first: Some(10),
+            },
+            BODY.to_vec(),
+        );
+        let mut file = NamedTempFile::new().unwrap();
+        let client = Client::new();
+        let progress_bar = ProgressBar::hidden();
+        let mut downloaded = 0;
+
+        let error = download_chunks(
+            &client,
+            &url,
+            file.as_file_mut(),
+            &mut downloaded,
+            &progress_bar,
+        );
+        assert!(matches!(error, Err(ChunkError::Transient(_))));
+        assert_eq!(downloaded, 10);
+
+        download_chunks(
+            &client,
+            &url,
+            file.as_file_mut(),
+            &mut downloaded,
+            &progress_bar,
+        )
+        .unwrap();
+        let content = fs::read(file.path()).unwrap();
+
+        assert_eq!(content, BODY);
+        assert_eq!(
+            *ranges.lock().unwrap(),
+            vec![None, Some("bytes=10-".to_string())]
+        );
+    }
+
+    #[test]
+    fn starts_over_when_the_server_ignores_the_range() {
+        let (url, ranges) = serve(
+            Behaviour {
+                status: 200,
+                honour_range: false,
+                truncate_first: Some(10),
+            },
+            BODY.to_vec(),
+        );
+        let mut file = NamedTempFile::new().unwrap();
+        let client = Client::new();
+        let progress_bar = ProgressBar::hidden();
+        let mut downloaded = 0;
+
+        assert!(download_chunks(
+            &client,
+            &url,
+            file.as_file_mut(),
+            &mut downloaded,
+            &progress_bar
+        )
+        .is_err());
+        download_chunks(
+            &client,
+            &url,
+            file.as_file_mut(),
+            &mut downloaded,
+            &progress_bar,
+        )
+        .unwrap();
+        let content = fs::read(file.path()).unwrap();
+
+        assert_eq!(content, BODY);
+        assert_eq!(downloaded, BODY.len() as u64);
+        assert_eq!(ranges.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn downloads_after_a_truncated_body() {
+        let (url, ranges) = serve(
+            Behaviour {
+                status: 200,
+                honour_range: true,
+                truncate_first: Some(20),
+            },
+            BODY.to_vec(),
+        );
+        let file = NamedTempFile::new().unwrap();
+
+        download_to_file(&Client::new(), &url, file.path()).unwrap();
+        let content = fs::read(file.path()).unwrap();
+
+        assert_eq!(content, BODY);
+        assert_eq!(
+            *ranges.lock().unwrap(),
+            vec![None, Some("bytes=20-".to_string())]
+        );
+    }
+
+    #[test]
+    fn fails_without_retrying_client_errors() {
+        let (url, ranges) = serve(
+            Behaviour {
+                status: 404,
+                honour_range: true,
+                truncate_first: None,
+            },
+            BODY.to_vec(),
+        );
+        let file = NamedTempFile::new().unwrap();
+
+        let error = download_to_file(&Client::new(), &url, file.path()).unwrap_err();
+
+        assert!(error.to_string().contains("404 Not Found"));
+        assert_eq!(ranges.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn retries_server_errors() {
+        let (url, _) = serve(
+            Behaviour {
+                status: 503,
+                honour_range: true,
+                truncate_first: None,
+            },
+            BODY.to_vec(),
+        );
+        let mut file = NamedTempFile::new().unwrap();
+        let mut downloaded = 0;
+
+        let error = download_chunks(
+            &Client::new(),
+            &url,
+            file.as_file_mut(),
+            &mut downloaded,
+            &ProgressBar::hidden(),
+        );
+
+        assert!(matches!(error, Err(ChunkError::Transient(_))));
+    }
+
+    /// Serve `checksum` as the `.sha256` file of a downloaded `BODY`
+    fn verify(status: u16, checksum: &str) -> Result<(), AppError> {
+        let (url, _) = serve(
+            Behaviour {
+                status,
+                honour_range: true,
+                truncate_first: None,
+            },
+            checksum.as_bytes().to_vec(),
+        );
+        let archive = temp_file(std::str::from_utf8(BODY).unwrap());
+        verify_checksum(&Client::new(), &url, archive.path())
+    }
+
+    fn body_digest() -> String {
+        hex::encode(Sha256::digest(BODY))
+    }
+
+    #[test]
+    fn accepts_a_matching_checksum() {
+        verify(200, &format!("{}\n", body_digest())).unwrap();
+    }
+
+    #[test]
+    fn accepts_a_checksum_followed_by_the_file_name() {
+        let checksum = format!(
+            "{}  stackql_linux_amd64.zip\n",
+            body_digest().to_uppercase()
+        );
+        verify(200, &checksum).unwrap();
+    }
+
+    #[test]
+    fn rejects_a_checksum_mismatch() {
+        let other = hex::encode(Sha256::digest(b"tampered"));
+        let error = verify(200, &other).unwrap_err().to_string();
+        assert!(error.contains("Checksum mismatch"), "{}", error);
+        assert!(error.contains(&format!("expected {}, got {}", other, body_digest())));
+    }
+
+    #[test]
+    fn fails_when_no_checksum_is_published() {
+        let error = verify(404, "").unwrap_err().to_string();
+        assert!(
+            error.contains("No SHA-256 checksum is published"),
+            "{}",
+            error
+        );
+    }
+}
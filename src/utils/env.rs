@@ -0,0 +1,219 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::Path;
+
+/// Dotenv file loaded from the stack directory when `--env-file` is not given
+pub const DEFAULT_ENV_FILE: &str = ".env";
+
+/// Parse a `KEY=VALUE` pair as given on the command line
+pub fn parse_env_var(pair: &str) -> Result<(String, String), String> {
+    match pair.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => {
+            Ok((key.trim().to_string(), value.to_string()))
+        }
+        _ => Err(format!("invalid variable '{}', expected KEY=VALUE", pair)),
+    }
+}
+
+/// Collect the variables available to a stack's templates, in increasing precedence:
+/// the process environment, the env file (`<stack_dir>/.env` unless another is given),
+/// then `overrides`
+pub fn stack_vars(
+    stack_dir: &Path,
+    env_file: Option<&Path>,
+    overrides: &BTreeMap<String, String>,
+) -> Result<BTreeMap<String, String>, String> {
+    let mut vars: BTreeMap<String, String> = env::vars().collect();
+
+    match env_file {
+        Some(env_file) => vars.extend(load_env_file(env_file)?),
+        _none => {
+            let default_env_file = stack_dir.join(DEFAULT_ENV_FILE);
+            if default_env_file.is_file() {
+                vars.extend(load_env_file(&default_env_file)?);
+            }
+        }
+    }
+
+    vars.extend(
+        overrides
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone())),
+    );
+    Ok(vars)
+}
+
+/// Load variables from a dotenv style file
+pub fn load_env_file(path: &Path) -> Result<BTreeMap<String, String>, String> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+
+    let mut vars = BTreeMap::new();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (key, value) =
+            parse_env_var(line).map_err(|e| format!("{}:{}: {}", path.display(), index + 1, e))?;
+        vars.insert(key, unquote(value.trim()));
+    }
+
+    Ok(vars)
+}
+
+/// Format variables as a dotenv style file that `load_env_file` and shells can read
+pub fn format_env_file(vars: &BTreeMap<String, String>) -> String {
+    vars.iter()
+        .map(|(key, value)| format!("{}={}\n", key, quote(value)))
+        .collect()
+}
+
+/// Quote a value when it holds characters a shell or dotenv parser would interpret,
+/// single quotes keep it literal unless it contains a single quote or a newline
+fn quote(value: &str) -> String {
+    let plain = value
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "-_./:@,+%".contains(c));
+    if plain {
+        value.to_string()
+    } else if !value.contains(['\'', '\n']) {
+        format!("'{}'", value)
+    } else {
+        let escaped = value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('$', "\\$")
+            .replace('`', "\\`")
+            .replace('\n', "\\n");
+        format!("\"{}\"", escaped)
+    }
+}
+
+/// Strip matching single or double quotes around a value, the escapes written by
+/// `quote` are expanded in double quoted values and other backslashes are kept
+fn unquote(value: &str) -> String {
+    if value.len() >= 2 && value.starts_with('\'') && value.ends_with('\'') {
+        return value[1..value.len() - 1].to_string();
+    }
+    if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+        let mut unescaped = String::new();
+        let mut chars = value[1..value.len() - 1].chars().peekable();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                unescaped.push(c);
+                continue;
+            }
+            match chars.peek() {
+                Some('n') => unescaped.push('\n'),
+                Some(&escaped) if "\\\"$`".contains(escaped) => unescaped.push(escaped),
+                _ => {
+                    unescaped.push(c);
+                    continue;
+                }
+            }
+            chars.next();
+        }
+        return unescaped;
+    }
+    value.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_file;
+
+    #[test]
+    fn parses_command_line_variables() {
+        assert_eq!(
+            parse_env_var(" AWS_REGION =us-east-1").unwrap(),
+            ("AWS_REGION".to_string(), "us-east-1".to_string())
+        );
+        assert_eq!(
+            parse_env_var("QUERY=a=b").unwrap(),
+            ("QUERY".to_string(), "a=b".to_string())
+        );
+        assert!(parse_env_var("AWS_REGION").is_err());
+        assert!(parse_env_var("=value").is_err());
+    }
+
+    #[test]
+    fn loads_dotenv_files() {
+        let file = temp_file(
+            "# credentials\n\nexport AWS_REGION=us-east-1\nNAME='my stack'\nPATH_VAR=\"C:\\path\"\n",
+        );
+        let vars = load_env_file(file.path()).unwrap();
+
+        assert_eq!(vars["AWS_REGION"], "us-east-1");
+        assert_eq!(vars["NAME"], "my stack");
+        assert_eq!(vars["PATH_VAR"], "C:\\path");
+        assert_eq!(vars.len(), 3);
+    }
+
+    #[test]
+    fn reports_the_line_of_an_invalid_entry() {
+        let file = temp_file("A=1\nnot a variable\n");
+        let error = load_env_file(file.path()).unwrap_err();
+
+        assert!(error.ends_with(":2: invalid variable 'not a variable', expected KEY=VALUE"));
+    }
+
+    #[test]
+    fn quotes_values_only_when_needed() {
+        let vars = BTreeMap::from([
+            ("ID".to_string(), "vpc-0abc".to_string()),
+            ("TAGS".to_string(), "[{\"Key\":\"a\"}]".to_string()),
+            ("EMPTY".to_string(), String::new()),
+        ]);
+        assert_eq!(
+            format_env_file(&vars),
+            "EMPTY=\nID=vpc-0abc\nTAGS='[{\"Key\":\"a\"}]'\n"
+        );
+    }
+
+    #[test]
+    fn round_trips_formatted_values() {
+        let vars: BTreeMap<String, String> = [
+            ("PLAIN", "vpc-0abc"),
+            ("SPACES", "my stack"),
+            ("JSON", "[{\"Key\":\"Name\",\"Value\":\"it's\"}]"),
+            ("MULTILINE", "line 1\nline 2"),
+            ("SHELL", "$HOME `pwd` \\ \"quoted\""),
+            ("WINDOWS", "C:\\path\\n"),
+            ("EMPTY", ""),
+        ]
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+
+        let file = temp_file(&format_env_file(&vars));
+        let loaded = load_env_file(file.path()).unwrap();
+
+        assert_eq!(loaded, vars);
+    }
+
+    #[test]
+    fn stack_vars_layer_env_file_and_overrides_over_the_process_environment() {
+        let stack_dir = tempfile::tempdir().unwrap();
+        fs::write(
+            stack_dir.path().join(DEFAULT_ENV_FILE),
+            "PATH=from-env-file\nREGION=us-east-1\n",
+        )
+        .unwrap();
+        let overrides = BTreeMap::from([("REGION".to_string(), "eu-west-1".to_string())]);
+
+        let vars = stack_vars(stack_dir.path(), None, &overrides).unwrap();
+        assert_eq!(vars["PATH"], "from-env-file");
+        assert_eq!(vars["REGION"], "eu-west-1");
+        assert!(env::vars().all(|(key, _)| vars.contains_key(&key)));
+
+        let other = temp_file("REGION=ap-south-1\n");
+        let vars = stack_vars(stack_dir.path(), Some(other.path()), &BTreeMap::new()).unwrap();
+        assert_eq!(vars["REGION"], "ap-south-1");
+        assert_ne!(vars.get("PATH").map(String::as_str), Some("from-env-file"));
+    }
+}
@@ -1,6 +1,7 @@
 pub mod binary;
 pub mod display;
 pub mod download;
+pub mod env;
 pub mod platform;
 pub mod query;
 pub mod server;
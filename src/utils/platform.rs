@@ -1,4 +1,8 @@
-#[derive(Debug, PartialEq)]
+use std::env::consts;
+use std::fmt;
+use std::fs;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Platform {
     Windows,
     MacOS,
@@ -19,6 +23,95 @@ pub fn get_platform() -> Platform {
     }
 }
 
+/// CPU architecture
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arch {
+    Amd64,
+    Arm64,
+    Other(&'static str),
+}
+
+/// C library of a Linux host, binaries linked against one do not run on the other
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Libc {
+    Glibc,
+    Musl,
+}
+
+/// Operating system, architecture and, on Linux, the C library of a host
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Target {
+    pub platform: Platform,
+    pub arch: Arch,
+    pub libc: Option<Libc>,
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let platform = match self.platform {
+            Platform::Windows => "windows",
+            Platform::MacOS => "macos",
+            Platform::Linux => "linux",
+            Platform::Unknown => consts::OS,
+        };
+        let arch = match self.arch {
+            Arch::Amd64 => "amd64",
+            Arch::Arm64 => "arm64",
+            Arch::Other(arch) => arch,
+        };
+        write!(f, "{}/{}", platform, arch)?;
+        match self.libc {
+            Some(Libc::Glibc) => write!(f, " (glibc)"),
+            Some(Libc::Musl) => write!(f, " (musl)"),
+            _none => Ok(()),
+        }
+    }
+}
+
+/// Determine the CPU architecture
+pub fn get_arch() -> Arch {
+    match consts::ARCH {
+        "x86_64" => Arch::Amd64,
+        "aarch64" => Arch::Arm64,
+        arch => Arch::Other(arch),
+    }
+}
+
+/// Determine the C library on Linux, `None` on other platforms. The host is checked
+/// rather than this build, since a glibc build can run in a container with both.
+pub fn get_libc() -> Option<Libc> {
+    if get_platform() != Platform::Linux {
+        return None;
+    }
+
+    // musl systems such as Alpine ship their dynamic loader as /lib/ld-musl-<arch>.so.1
+    let has_musl_loader = fs::read_dir("/lib")
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .any(|entry| entry.file_name().to_string_lossy().starts_with("ld-musl-"))
+        })
+        .unwrap_or(false);
+    let has_glibc_loader = ["/lib64/ld-linux-x86-64.so.2", "/lib/ld-linux-aarch64.so.1"]
+        .iter()
+        .any(|path| fs::metadata(path).is_ok());
+
+    if has_musl_loader && !has_glibc_loader {
+        Some(Libc::Musl)
+    } else {
+        Some(Libc::Glibc)
+    }
+}
+
+/// Determine the operating system, architecture and C library of this host
+pub fn get_target() -> Target {
+    Target {
+        platform: get_platform(),
+        arch: get_arch(),
+        libc: get_libc(),
+    }
+}
+
 /// Get the appropriate binary name based on platform
 pub fn get_binary_name() -> String {
     match get_platform() {
@@ -1,83 +1,422 @@
-use crate::utils::server::{is_server_running, start_server, ServerOptions};
-use postgres::{Client, NoTls};
+use crate::utils::server::{is_local_host, start_server, ServerOptions};
+use postgres::{Client, Config, NoTls, SimpleQueryMessage};
+use serde_json::{Number, Value as JsonValue};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+/// Type of a column, inferred from the text of its values since the simple query
+/// protocol does not report one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnType {
+    /// Every value is NULL
+    Null,
+    Boolean,
+    Integer,
+    Float,
+    /// JSON objects or arrays
+    Json,
+    Text,
+}
 
 pub struct QueryResultColumn {
     pub name: String,
+    pub column_type: ColumnType,
 }
 
+/// A row of decoded values, `None` is NULL
 pub struct QueryResultRow {
-    pub values: Vec<String>,
+    pub values: Vec<Option<JsonValue>>,
+}
+
+impl QueryResultRow {
+    /// Values formatted for display, NULL is shown as `NULL`
+    pub fn display_values(&self) -> Vec<String> {
+        self.values
+            .iter()
+            .map(|value| match value {
+                Some(JsonValue::String(s)) => s.clone(),
+                Some(value) => value.to_string(),
+                _none => "NULL".to_string(),
+            })
+            .collect()
+    }
 }
 
 pub enum QueryResult {
     Data {
         columns: Vec<QueryResultColumn>,
         rows: Vec<QueryResultRow>,
-        #[allow(dead_code)]
-        notices: Vec<String>,
     },
     Command(String),
     Empty,
 }
 
-pub fn execute_query(query: &str, port: u16) -> Result<QueryResult, String> {
-    if !is_server_running(port) {
-        let options = ServerOptions {
+/// A connection to a stackql server, opened on first use and reused across queries
+pub struct StackqlSession {
+    host: String,
+    port: u16,
+    client: Option<Client>,
+    /// Notices received while running the most recent query
+    notices: Arc<Mutex<Vec<String>>>,
+}
+
+impl StackqlSession {
+    /// Session that connects lazily on its first query
+    pub fn new(host: &str, port: u16) -> Self {
+        Self {
+            host: host.to_string(),
             port,
-            ..Default::default()
-        };
-        start_server(&options).map_err(|e| format!("Failed to start server: {}", e))?;
-    }
-
-    let connection_string = format!(
-        "host=localhost port={} user=postgres dbname=stackql application_name=stackql",
-        port
-    );
-    let mut client = Client::connect(&connection_string, NoTls)
-        .map_err(|e| format!("Failed to connect to server: {}", e))?;
-
-    match client.simple_query(query) {
-        Ok(results) => {
-            let mut columns = Vec::new();
-            let mut rows = Vec::new();
-            let mut command_message = String::new();
-
-            for result in results {
-                match result {
-                    postgres::SimpleQueryMessage::Row(row) => {
-                        if columns.is_empty() {
-                            for i in 0..row.len() {
-                                columns.push(QueryResultColumn {
-                                    name: row.columns()[i].name().to_string(),
-                                });
-                            }
-                        }
-
-                        let row_values = (0..row.len())
-                            .map(|i| row.get(i).unwrap_or("NULL").to_string())
-                            .collect();
-
-                        rows.push(QueryResultRow { values: row_values });
-                    }
-                    postgres::SimpleQueryMessage::CommandComplete(cmd) => {
-                        command_message = cmd.to_string();
-                    }
-                    _ => {}
-                }
+            client: None,
+            notices: Arc::default(),
+        }
+    }
+
+    /// Connect to the server, starting a local server first if none is accepting connections
+    pub fn open(host: &str, port: u16) -> Result<Self, String> {
+        let mut session = Self::new(host, port);
+        if session.client().is_err() && is_local_host(host) {
+            let options = ServerOptions {
+                port,
+                ..Default::default()
+            };
+            start_server(&options).map_err(|e| format!("Failed to start server: {}", e))?;
+        }
+        session.client()?;
+        Ok(session)
+    }
+
+    /// Run a query, reconnecting once if the connection has been lost
+    pub fn query(&mut self, query: &str) -> Result<QueryResult, String> {
+        self.notices.lock().unwrap().clear();
+        let result = self.client()?.simple_query(query);
+        let messages = match result {
+            Ok(messages) => messages,
+            Err(_)
+                if self
+                    .client
+                    .as_ref()
+                    .is_some_and(|client| client.is_closed()) =>
+            {
+                self.client = None;
+                self.client()?
+                    .simple_query(query)
+                    .map_err(|e| format!("Query execution failed: {}", e))?
             }
+            Err(e) => return Err(format!("Query execution failed: {}", e)),
+        };
+        Ok(to_query_result(messages))
+    }
+
+    /// Notices the server sent while running the most recent query, e.g. provider HTTP errors
+    pub fn notices(&self) -> Vec<String> {
+        self.notices.lock().unwrap().clone()
+    }
 
-            if !columns.is_empty() {
-                Ok(QueryResult::Data {
-                    columns,
-                    rows,
-                    notices: vec![],
+    fn client(&mut self) -> Result<&mut Client, String> {
+        if self.client.is_none() {
+            let connection_string = format!(
+                "host={} port={} user=postgres dbname=stackql application_name=stackql",
+                self.host, self.port
+            );
+            let notices = Arc::clone(&self.notices);
+            let client = Config::from_str(&connection_string)
+                .map_err(|e| format!("invalid connection settings: {}", e))?
+                .notice_callback(move |notice| {
+                    notices.lock().unwrap().push(notice.message().to_string())
                 })
-            } else if !command_message.is_empty() {
-                Ok(QueryResult::Command(command_message))
-            } else {
-                Ok(QueryResult::Empty)
+                .connect(NoTls)
+                .map_err(|e| format!("could not connect to {}:{}: {}", self.host, self.port, e))?;
+            self.client = Some(client);
+        }
+        Ok(self.client.as_mut().unwrap())
+    }
+}
+
+/// Sessions shared between worker threads, each task borrows one for its duration
+pub struct SessionPool {
+    host: String,
+    port: u16,
+    idle: Mutex<Vec<StackqlSession>>,
+}
+
+impl SessionPool {
+    /// Pool seeded with an open session, further sessions are created as workers need them
+    pub fn new(session: StackqlSession) -> Self {
+        Self {
+            host: session.host.clone(),
+            port: session.port,
+            idle: Mutex::new(vec![session]),
+        }
+    }
+
+    /// Run `f` with a session that no other thread is using
+    pub fn with_session<T>(&self, f: impl FnOnce(&mut StackqlSession) -> T) -> T {
+        let idle = self.idle.lock().unwrap().pop();
+        let mut session = idle.unwrap_or_else(|| StackqlSession::new(&self.host, self.port));
+        let result = f(&mut session);
+        self.idle.lock().unwrap().push(session);
+        result
+    }
+}
+
+/// The first notice reporting a failed provider request, StackQL reports these as
+/// "http response status code: 403, response body: ..." rather than as query errors
+pub fn provider_error(notices: &[String]) -> Option<&String> {
+    notices.iter().find(|notice| {
+        notice
+            .split("status code:")
+            .nth(1)
+            .and_then(|rest| {
+                let code: String = rest
+                    .trim_start()
+                    .chars()
+                    .take_while(char::is_ascii_digit)
+                    .collect();
+                code.parse::<u16>().ok()
+            })
+            .is_some_and(|code| code >= 400)
+    })
+}
+
+fn to_query_result(messages: Vec<SimpleQueryMessage>) -> QueryResult {
+    let mut names = Vec::new();
+    let mut raw_rows: Vec<Vec<Option<String>>> = Vec::new();
+    let mut command_message = String::new();
+
+    for message in messages {
+        match message {
+            SimpleQueryMessage::RowDescription(columns) => {
+                names = columns.iter().map(|c| c.name().to_string()).collect();
+            }
+            SimpleQueryMessage::Row(row) => {
+                if names.is_empty() {
+                    names = row.columns().iter().map(|c| c.name().to_string()).collect();
+                }
+                raw_rows.push(
+                    (0..row.len())
+                        .map(|i| row.get(i).map(|value| value.to_string()))
+                        .collect(),
+                );
+            }
+            SimpleQueryMessage::CommandComplete(cmd) => {
+                command_message = cmd.to_string();
             }
+            _ => {}
         }
-        Err(e) => Err(format!("Query execution failed: {}", e)),
+    }
+
+    if !names.is_empty() {
+        let (columns, rows) = decode_columns(names, raw_rows);
+        QueryResult::Data { columns, rows }
+    } else if !command_message.is_empty() {
+        QueryResult::Command(command_message)
+    } else {
+        QueryResult::Empty
+    }
+}
+
+/// Infer the type of each column from its values and decode the rows, NULL values stay
+/// `None` so they are told apart from the text "NULL"
+fn decode_columns(
+    names: Vec<String>,
+    raw_rows: Vec<Vec<Option<String>>>,
+) -> (Vec<QueryResultColumn>, Vec<QueryResultRow>) {
+    let column_types: Vec<ColumnType> = (0..names.len())
+        .map(|i| infer_column_type(raw_rows.iter().filter_map(|row| row[i].as_deref())))
+        .collect();
+
+    let columns = names
+        .into_iter()
+        .zip(&column_types)
+        .map(|(name, column_type)| QueryResultColumn {
+            name,
+            column_type: *column_type,
+        })
+        .collect();
+
+    let rows = raw_rows
+        .into_iter()
+        .map(|row| QueryResultRow {
+            values: row
+                .into_iter()
+                .zip(&column_types)
+                .map(|(value, column_type)| value.map(|v| decode_value(v, *column_type)))
+                .collect(),
+        })
+        .collect();
+
+    (columns, rows)
+}
+
+/// Type a single value would decode as. Numbers only qualify if decoding them would not
+/// change their text, so identifiers like "012345678901" stay text.
+fn value_type(value: &str) -> ColumnType {
+    if value == "true" || value == "false" {
+        ColumnType::Boolean
+    } else if value.parse::<i64>().is_ok_and(|n| n.to_string() == value) {
+        ColumnType::Integer
+    } else if value
+        .parse::<f64>()
+        .ok()
+        .and_then(Number::from_f64)
+        .is_some_and(|n| n.to_string() == value)
+    {
+        ColumnType::Float
+    } else if (value.starts_with('{') || value.starts_with('['))
+        && serde_json::from_str::<JsonValue>(value).is_ok()
+    {
+        ColumnType::Json
+    } else {
+        ColumnType::Text
+    }
+}
+
+/// The narrowest type every non-NULL value in a column decodes as
+fn infer_column_type<'a>(values: impl Iterator<Item = &'a str>) -> ColumnType {
+    values
+        .map(value_type)
+        .fold(ColumnType::Null, |inferred, value| {
+            match (inferred, value) {
+                (ColumnType::Null, value) => value,
+                (inferred, value) if inferred == value => inferred,
+                (ColumnType::Integer, ColumnType::Float)
+                | (ColumnType::Float, ColumnType::Integer) => ColumnType::Float,
+                _ => ColumnType::Text,
+            }
+        })
+}
+
+fn decode_value(value: String, column_type: ColumnType) -> JsonValue {
+    let decoded = match column_type {
+        ColumnType::Boolean => Some(JsonValue::Bool(value == "true")),
+        ColumnType::Integer | ColumnType::Float => serde_json::from_str(&value).ok(),
+        ColumnType::Json => serde_json::from_str(&value).ok(),
+        ColumnType::Null | ColumnType::Text => None,
+    };
+    decoded.unwrap_or(JsonValue::String(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn raw(rows: &[&[Option<&str>]]) -> Vec<Vec<Option<String>>> {
+        rows.iter()
+            .map(|row| row.iter().map(|value| value.map(str::to_string)).collect())
+            .collect()
+    }
+
+    #[test]
+    fn infers_the_type_of_a_value() {
+        assert_eq!(value_type("123456789012"), ColumnType::Integer);
+        assert_eq!(value_type("-7"), ColumnType::Integer);
+        assert_eq!(value_type("1.5"), ColumnType::Float);
+        assert_eq!(value_type("true"), ColumnType::Boolean);
+        assert_eq!(value_type("{\"Key\":\"Name\"}"), ColumnType::Json);
+        assert_eq!(value_type("[1, 2]"), ColumnType::Json);
+        assert_eq!(value_type("vpc-0abc"), ColumnType::Text);
+        assert_eq!(value_type("NULL"), ColumnType::Text);
+        assert_eq!(value_type("{not json"), ColumnType::Text);
+    }
+
+    #[test]
+    fn keeps_numbers_as_text_when_decoding_would_change_them() {
+        assert_eq!(value_type("012345678901"), ColumnType::Text);
+        assert_eq!(value_type("1.50"), ColumnType::Text);
+        assert_eq!(value_type("+1"), ColumnType::Text);
+        assert_eq!(value_type("1e3"), ColumnType::Text);
+    }
+
+    #[test]
+    fn infers_the_narrowest_type_of_a_column() {
+        assert_eq!(
+            infer_column_type(["1", "2"].into_iter()),
+            ColumnType::Integer
+        );
+        assert_eq!(
+            infer_column_type(["1", "2.5"].into_iter()),
+            ColumnType::Float
+        );
+        assert_eq!(
+            infer_column_type(["1", "vpc-1"].into_iter()),
+            ColumnType::Text
+        );
+        assert_eq!(
+            infer_column_type(["true", "1"].into_iter()),
+            ColumnType::Text
+        );
+        assert_eq!(infer_column_type([].into_iter()), ColumnType::Null);
+    }
+
+    #[test]
+    fn decodes_values_by_column_type() {
+        let (columns, rows) = decode_columns(
+            vec![
+                "account".into(),
+                "enabled".into(),
+                "ratio".into(),
+                "tags".into(),
+            ],
+            raw(&[
+                &[
+                    Some("123456789012"),
+                    Some("true"),
+                    Some("1"),
+                    Some("[{\"Key\":\"a\"}]"),
+                ],
+                &[Some("012345678901"), Some("false"), Some("0.5"), Some("[]")],
+            ]),
+        );
+
+        let types: Vec<ColumnType> = columns.iter().map(|c| c.column_type).collect();
+        assert_eq!(
+            types,
+            [
+                ColumnType::Text,
+                ColumnType::Boolean,
+                ColumnType::Float,
+                ColumnType::Json
+            ]
+        );
+        // a column with any value that is not a number keeps every value as text
+        assert_eq!(
+            rows[0].values,
+            [
+                Some(json!("123456789012")),
+                Some(json!(true)),
+                Some(json!(1)),
+                Some(json!([{"Key": "a"}]))
+            ]
+        );
+        assert_eq!(rows[1].values[0], Some(json!("012345678901")));
+        assert_eq!(rows[1].values[2], Some(json!(0.5)));
+    }
+
+    #[test]
+    fn decodes_a_tag_value_of_true_as_a_boolean() {
+        let (_, rows) = decode_columns(vec!["value".into()], raw(&[&[Some("true")]]));
+        assert_eq!(rows[0].values, [Some(json!(true))]);
+    }
+
+    #[test]
+    fn tells_null_apart_from_the_text_null() {
+        let (columns, rows) = decode_columns(
+            vec!["id".into(), "description".into()],
+            raw(&[&[Some("1"), None], &[Some("2"), Some("NULL")]]),
+        );
+
+        assert_eq!(columns[1].column_type, ColumnType::Text);
+        assert_eq!(rows[0].values[1], None);
+        assert_eq!(rows[1].values[1], Some(json!("NULL")));
+        assert_eq!(rows[0].display_values(), ["1", "NULL"]);
+    }
+
+    #[test]
+    fn infers_null_for_a_column_of_nulls() {
+        let (columns, rows) = decode_columns(vec!["deleted_at".into()], raw(&[&[None], &[None]]));
+
+        assert_eq!(columns[0].column_type, ColumnType::Null);
+        assert!(rows.iter().all(|row| row.values == [None]));
     }
 }
@@ -1,10 +1,22 @@
-use crate::utils::binary::get_binary_path;
+use crate::utils::binary::{cache_dir, get_binary_path};
+use crate::utils::display::print_line;
 use colored::*;
-use std::fs::OpenOptions;
-use std::path::Path;
+use postgres::{Config, NoTls};
+use std::fs::{self, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::process;
 use std::process::{Command as ProcessCommand, Stdio};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+/// Host the stackql server is reached on unless told otherwise
+pub const DEFAULT_SERVER_HOST: &str = "localhost";
+
+/// Port the stackql server listens on unless told otherwise
+pub const DEFAULT_SERVER_PORT: u16 = 5444;
 
 pub struct ServerOptions {
     pub port: u16,
@@ -15,136 +27,129 @@ pub struct ServerOptions {
 impl Default for ServerOptions {
     fn default() -> Self {
         Self {
-            port: 5444,
+            port: DEFAULT_SERVER_PORT,
             registry: None,
             additional_args: Vec::new(),
         }
     }
 }
 
-/// Check if the stackql server is running
-pub fn is_server_running(port: u16) -> bool {
-    // Check using process name and port
-    if cfg!(target_os = "windows") {
-        let output = ProcessCommand::new("tasklist")
-            .output()
-            .unwrap_or_else(|_| panic!("Failed to execute tasklist"));
+/// Whether a host refers to this machine, only servers on this machine are started and stopped
+pub fn is_local_host(host: &str) -> bool {
+    matches!(host, "localhost" | "127.0.0.1" | "::1")
+}
 
-        let output_str = String::from_utf8_lossy(&output.stdout);
-        output_str.contains("stackql") && output_str.contains(&port.to_string())
-    } else {
-        // Try multiple pattern variations to be more robust
-        let patterns = [
-            format!("stackql.*--pgsrv.port {}", port),
-            format!("stackql.*--pgsrv.port={}", port),
-            format!("stackql.*pgsrv.port {}", port),
-            format!("stackql.*pgsrv.port={}", port),
-        ];
-
-        for pattern in patterns {
-            let output = ProcessCommand::new("pgrep")
-                .arg("-f")
-                .arg(&pattern)
-                .output();
-
-            if let Ok(output) = output {
-                if !output.stdout.is_empty() {
-                    return true;
-                }
-            }
-        }
+/// Port of the server started by this process, the only server a command stops when it ends
+static STARTED_SERVER: Mutex<Option<u16>> = Mutex::new(None);
 
-        // Fallback: Just check for any stackql process
-        let output = ProcessCommand::new("pgrep")
-            .arg("-f")
-            .arg("stackql")
-            .output();
-
-        if let Ok(output) = output {
-            if !output.stdout.is_empty() {
-                // Further check if this is likely our server by examining the process details
-                let stdout_content = String::from_utf8_lossy(&output.stdout);
-                let pid = stdout_content.trim();
-
-                let ps_output = ProcessCommand::new("ps")
-                    .arg("-p")
-                    .arg(pid)
-                    .arg("-o")
-                    .arg("args")
-                    .output();
-
-                if let Ok(ps_output) = ps_output {
-                    let ps_str = String::from_utf8_lossy(&ps_output.stdout);
-                    return ps_str.contains(&port.to_string()) && ps_str.contains("srv");
-                }
-            }
-        }
+/// Leave the server started by this process running, set by `--keep-server`
+static KEEP_STARTED_SERVER: AtomicBool = AtomicBool::new(false);
+
+/// How long to wait for a newly started server to accept connections
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long to wait for a stopped server to exit
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Interval between readiness and shutdown checks
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
 
-        false
+/// File recording the PID of the server started on a port, kept per user so one user
+/// never acts on another user's server
+fn pidfile_path(port: u16) -> PathBuf {
+    cache_dir().join(format!("server-{}.pid", port))
+}
+
+/// Check if a stackql server is accepting connections on the port
+pub fn is_server_running(port: u16) -> bool {
+    let connection_string = format!(
+        "host={} port={} user=postgres dbname=stackql application_name=stackql",
+        DEFAULT_SERVER_HOST, port
+    );
+    match Config::from_str(&connection_string) {
+        Ok(mut config) => config
+            .connect_timeout(Duration::from_secs(2))
+            .connect(NoTls)
+            .is_ok(),
+        Err(_) => false,
     }
 }
 
-/// Get the PID of the running stackql server
+/// Get the PID of the server started on the port, from its pidfile.
+/// Servers started by other tools have no pidfile and are never reported.
 pub fn get_server_pid(port: u16) -> Option<u32> {
-    if cfg!(target_os = "windows") {
-        let output = ProcessCommand::new("wmic")
-            .arg("process")
-            .arg("where")
+    let path = pidfile_path(port);
+    let pid = fs::read_to_string(&path).ok()?.trim().parse::<u32>().ok()?;
+    if is_process_alive(pid) && is_server_process(pid, port) {
+        Some(pid)
+    } else {
+        // the server exited without being stopped, e.g. it crashed or the machine rebooted,
+        // and its PID may since have been reused by an unrelated process
+        fs::remove_file(&path).ok();
+        None
+    }
+}
+
+/// Whether the process runs a stackql server on the port, as started by `start_server`
+fn is_server_process(pid: u32, port: u16) -> bool {
+    let output = if cfg!(target_os = "windows") {
+        ProcessCommand::new("powershell")
+            .arg("-NoProfile")
+            .arg("-Command")
             .arg(format!(
-                "CommandLine like '%stackql%--pgsrv.port={}%'",
-                port
+                "(Get-CimInstance Win32_Process -Filter 'ProcessId={}').CommandLine",
+                pid
             ))
-            .arg("get")
-            .arg("ProcessId")
             .output()
-            .ok()?;
-
-        let output_str = String::from_utf8_lossy(&output.stdout);
-        let lines: Vec<&str> = output_str.lines().collect();
-        if lines.len() >= 2 {
-            lines[1].trim().parse::<u32>().ok()
-        } else {
-            None
-        }
     } else {
-        // For Linux/macOS, let's try multiple pattern variations
-        let patterns = [
-            format!("stackql.*--pgsrv.port {}", port),
-            format!("stackql.*--pgsrv.port={}", port),
-            format!("stackql.*pgsrv.port {}", port),
-            format!("stackql.*pgsrv.port={}", port),
-        ];
-
-        for pattern in patterns {
-            let output = ProcessCommand::new("pgrep")
-                .arg("-f")
-                .arg(&pattern)
-                .output()
-                .ok()?;
-
-            if !output.stdout.is_empty() {
-                let stdout_content = String::from_utf8_lossy(&output.stdout);
-                let pid_str = stdout_content.trim();
-                if let Ok(pid) = pid_str.parse::<u32>() {
-                    return Some(pid);
-                }
-            }
-        }
-
-        // Try a more general approach to find the stackql server
-        let output = ProcessCommand::new("pgrep")
-            .arg("-f")
-            .arg("stackql.*srv")
+        ProcessCommand::new("ps")
+            .arg("-o")
+            .arg("args=")
+            .arg("-p")
+            .arg(pid.to_string())
             .output()
-            .ok()?;
+    };
 
-        if !output.stdout.is_empty() {
-            let stdout_content = String::from_utf8_lossy(&output.stdout);
-            let pid_str = stdout_content.trim();
-            pid_str.parse::<u32>().ok()
-        } else {
-            None
+    match output {
+        Ok(output) if output.status.success() => {
+            is_server_command_line(&String::from_utf8_lossy(&output.stdout), port)
         }
+        _ => false,
+    }
+}
+
+fn is_server_command_line(command_line: &str, port: u16) -> bool {
+    let args: Vec<&str> = command_line.split_whitespace().collect();
+    let port = port.to_string();
+    command_line.contains("stackql")
+        && args
+            .windows(2)
+            .any(|pair| pair[0] == "--pgsrv.port" && pair[1] == port)
+        && args.contains(&"srv")
+}
+
+fn is_process_alive(pid: u32) -> bool {
+    if cfg!(target_os = "windows") {
+        ProcessCommand::new("tasklist")
+            .arg("/FI")
+            .arg(format!("PID eq {}", pid))
+            .arg("/NH")
+            .output()
+            .map(|output| String::from_utf8_lossy(&output.stdout).contains(&pid.to_string()))
+            .unwrap_or(false)
+    } else {
+        // a server started by this process stays a zombie after exiting, which is not alive
+        ProcessCommand::new("ps")
+            .arg("-o")
+            .arg("stat=")
+            .arg("-p")
+            .arg(pid.to_string())
+            .output()
+            .map(|output| {
+                let stat = String::from_utf8_lossy(&output.stdout);
+                output.status.success() && !stat.trim().is_empty() && !stat.trim().starts_with('Z')
+            })
+            .unwrap_or(false)
     }
 }
 
@@ -157,9 +162,10 @@ pub fn start_server(options: &ServerOptions) -> Result<u32, String> {
 
     // Check if server is already running
     if is_server_running(options.port) {
-        println!(
-            "{}",
-            format!("Server is already running on port {}", options.port).yellow()
+        print_line(
+            &format!("Server is already running on port {}", options.port)
+                .yellow()
+                .to_string(),
         );
         return Ok(get_server_pid(options.port).unwrap_or(0));
     }
@@ -196,43 +202,108 @@ pub fn start_server(options: &ServerOptions) -> Result<u32, String> {
         .map_err(|e| format!("Failed to open log file: {}", e))?;
 
     // Start the server
-    let child = cmd
+    let mut child = cmd
         .stdout(Stdio::from(log_file.try_clone().unwrap()))
         .stderr(Stdio::from(log_file))
         .spawn()
         .map_err(|e| format!("Failed to start server: {}", e))?;
 
     let pid = child.id();
+    let pidfile = pidfile_path(options.port);
+    if let Some(dir) = pidfile.parent() {
+        fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    }
+    fs::write(&pidfile, pid.to_string()).map_err(|e| format!("Failed to write pidfile: {}", e))?;
 
-    // Wait a bit for the server to start
-    println!(
-        "{}",
-        format!("Starting stackql server with PID: {}", pid).green()
+    print_line(
+        &format!("Starting stackql server with PID: {}", pid)
+            .green()
+            .to_string(),
     );
-    thread::sleep(Duration::from_secs(5));
 
-    if is_server_running(options.port) {
-        println!("{}", "Server started successfully".green());
-        Ok(pid)
-    } else {
-        Err("Server failed to start properly".to_string())
+    // Wait until the server accepts connections, or give up if it exits first
+    let started = Instant::now();
+    loop {
+        if is_server_running(options.port) {
+            print_line(&"Server started successfully".green().to_string());
+            *STARTED_SERVER.lock().unwrap() = Some(options.port);
+            return Ok(pid);
+        }
+
+        if let Ok(Some(status)) = child.try_wait() {
+            fs::remove_file(pidfile_path(options.port)).ok();
+            return Err(format!(
+                "Server exited during startup ({}), see {} for details",
+                status,
+                log_path.display()
+            ));
+        }
+
+        if started.elapsed() > STARTUP_TIMEOUT {
+            child.kill().ok();
+            fs::remove_file(pidfile_path(options.port)).ok();
+            return Err(format!(
+                "Server did not accept connections within {} seconds, see {} for details",
+                STARTUP_TIMEOUT.as_secs(),
+                log_path.display()
+            ));
+        }
+
+        thread::sleep(POLL_INTERVAL);
     }
 }
 
-/// Stop the stackql server
-pub fn stop_server(port: u16) -> Result<(), String> {
-    if !is_server_running(port) {
+/// Keep the server started by this process running after the command ends
+pub fn set_keep_started_server(keep: bool) {
+    KEEP_STARTED_SERVER.store(keep, Ordering::SeqCst);
+}
+
+/// Stop the server started by this process, if any. Servers started by an earlier
+/// command or by the user are left running.
+pub fn stop_started_server() -> Result<(), String> {
+    let port = match STARTED_SERVER.lock().unwrap().take() {
+        Some(port) => port,
+        _none => return Ok(()),
+    };
+
+    if KEEP_STARTED_SERVER.load(Ordering::SeqCst) {
+        print_line(
+            &format!("Leaving stackql server running on port {}", port)
+                .yellow()
+                .to_string(),
+        );
         return Ok(());
     }
 
+    stop_server(port)
+}
+
+/// Exit after stopping the server started by this process, for commands that fail
+/// after the server may have been started
+pub fn exit_stopping_server(code: i32) -> ! {
+    stop_started_server().ok();
+    process::exit(code)
+}
+
+/// Stop the stackql server started on the port. Only the process recorded in the
+/// pidfile is stopped, a server started some other way is left running.
+pub fn stop_server(port: u16) -> Result<(), String> {
     let pid = match get_server_pid(port) {
         Some(pid) => pid,
-        _none => return Err("Could not determine server PID".to_string()),
+        _none if is_server_running(port) => {
+            return Err(format!(
+                "The server on port {} was not started by stackql-deploy, leaving it running",
+                port
+            ))
+        }
+        _none => return Ok(()),
     };
 
-    println!(
-        "{}",
-        format!("Stopping stackql server with PID: {}", pid).yellow()
+    print_line(
+        &format!("Stopping stackql server with PID: {}", pid)
+            .yellow()
+            .to_string(),
     );
 
     if cfg!(target_os = "windows") {
@@ -249,13 +320,42 @@ pub fn stop_server(port: u16) -> Result<(), String> {
             .map_err(|e| format!("Failed to stop server: {}", e))?;
     }
 
-    // Wait a bit to verify it's stopped
-    thread::sleep(Duration::from_secs(1));
+    // Wait for the process to exit
+    let stopping = Instant::now();
+    while is_process_alive(pid) {
+        if stopping.elapsed() > SHUTDOWN_TIMEOUT {
+            return Err(format!(
+                "Server is still running {} seconds after stop attempt",
+                SHUTDOWN_TIMEOUT.as_secs()
+            ));
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+
+    fs::remove_file(pidfile_path(port)).ok();
+    print_line(&"Server stopped successfully".green().to_string());
+    Ok(())
+}
 
-    if !is_server_running(port) {
-        println!("{}", "Server stopped successfully".green());
-        Ok(())
-    } else {
-        Err("Server is still running after stop attempt".to_string())
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognises_the_server_command_line() {
+        assert!(is_server_command_line(
+            "/home/me/.cache/stackql-deploy/bin/stackql --pgsrv.port 5444 srv",
+            5444
+        ));
+        assert!(is_server_command_line(
+            "C:\\Program Files\\stackql\\stackql.exe --pgsrv.port 5444 --registry {} srv",
+            5444
+        ));
+        assert!(!is_server_command_line(
+            "/usr/bin/stackql --pgsrv.port 5445 srv",
+            5444
+        ));
+        assert!(!is_server_command_line("/usr/bin/stackql shell", 5444));
+        assert!(!is_server_command_line("/usr/sbin/sshd -D", 5444));
     }
 }
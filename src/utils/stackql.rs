@@ -12,6 +12,16 @@ pub struct Provider {
     pub version: String,
 }
 
+/// Normalize a stackql release version to the `v1.2.3` form used in release URLs
+pub fn normalize_version(version: &str) -> Option<String> {
+    let digits = version.trim().trim_start_matches('v');
+    let valid = !digits.is_empty()
+        && digits
+            .split('.')
+            .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()));
+    valid.then(|| format!("v{}", digits))
+}
+
 pub fn get_version() -> Result<VersionInfo, String> {
     let binary_path = match get_binary_path() {
         Some(path) => path,